@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS karma_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    delta INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_karma_events_name ON karma_events(name);
+CREATE INDEX IF NOT EXISTS idx_karma_events_created_at ON karma_events(created_at);
@@ -0,0 +1,5 @@
+ALTER TABLE karma_events ADD COLUMN giver TEXT;
+ALTER TABLE karma_events ADD COLUMN channel_id TEXT;
+ALTER TABLE karma_events ADD COLUMN reason TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_karma_events_giver ON karma_events(giver);
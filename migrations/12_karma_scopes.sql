@@ -0,0 +1,19 @@
+-- Karma can optionally be kept separately per channel (or group of
+-- channels). The empty scope is used when karma is global, which is where
+-- all existing karma lives.
+CREATE TABLE karma_scoped (
+    scope TEXT NOT NULL DEFAULT '',
+    name TEXT NOT NULL,
+    score INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (scope, name)
+);
+
+INSERT INTO karma_scoped (scope, name, score) SELECT '', name, score FROM karma;
+
+DROP TABLE karma;
+
+ALTER TABLE karma_scoped RENAME TO karma;
+
+CREATE INDEX IF NOT EXISTS idx_karma_name ON karma(name);
+
+ALTER TABLE karma_events ADD COLUMN scope TEXT NOT NULL DEFAULT '';
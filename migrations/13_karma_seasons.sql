@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS karma_seasons (
+    season TEXT NOT NULL,
+    scope TEXT NOT NULL DEFAULT '',
+    name TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    ended_at INTEGER NOT NULL,
+    PRIMARY KEY (season, scope, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_karma_seasons_ended_at ON karma_seasons(ended_at);
@@ -0,0 +1,17 @@
+-- Quotes get IDs so they can be referenced and deleted. Existing quotes
+-- keep their rowid as their ID. AUTOINCREMENT makes sure the ID of a
+-- deleted quote is never handed out again.
+CREATE TABLE quotes_with_ids (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    nick TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    grabber TEXT
+);
+
+INSERT INTO quotes_with_ids (id, nick, quote) SELECT rowid, nick, quote FROM quotes;
+
+DROP TABLE quotes;
+
+ALTER TABLE quotes_with_ids RENAME TO quotes;
+
+CREATE INDEX IF NOT EXISTS idx_quotes_nick ON quotes(nick);
@@ -0,0 +1,8 @@
+-- Where and when quotes were said and grabbed. These are all NULL for
+-- quotes grabbed before they were recorded.
+ALTER TABLE quotes ADD COLUMN channel_id TEXT;
+ALTER TABLE quotes ADD COLUMN backend TEXT;
+ALTER TABLE quotes ADD COLUMN said_at INTEGER;
+ALTER TABLE quotes ADD COLUMN grabbed_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_quotes_channel_id ON quotes(channel_id);
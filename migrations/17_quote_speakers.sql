@@ -0,0 +1,11 @@
+-- Quotes can span several speakers, so who said a quote is tracked
+-- separately from the nick it's shown under.
+CREATE TABLE IF NOT EXISTS quote_speakers (
+    quote_id INTEGER NOT NULL REFERENCES quotes(id) ON DELETE CASCADE,
+    nick TEXT NOT NULL,
+    PRIMARY KEY (quote_id, nick)
+);
+
+CREATE INDEX IF NOT EXISTS idx_quote_speakers_nick ON quote_speakers(nick);
+
+INSERT INTO quote_speakers (quote_id, nick) SELECT id, nick FROM quotes;
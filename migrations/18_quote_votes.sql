@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS quote_votes (
+    quote_id INTEGER NOT NULL REFERENCES quotes(id) ON DELETE CASCADE,
+    voter TEXT NOT NULL,
+    vote INTEGER NOT NULL CHECK (vote IN (-1, 1)),
+    PRIMARY KEY (quote_id, voter)
+);
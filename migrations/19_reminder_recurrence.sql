@@ -0,0 +1,5 @@
+-- Recurring reminders store their rule, like "every weekday at 09:55", and
+-- the timezone it's in, so the next occurrence can be found after each one is
+-- delivered.
+ALTER TABLE reminders ADD COLUMN recurrence TEXT;
+ALTER TABLE reminders ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,25 @@
+-- Reminders can be sent as a private message instead of to a channel, which
+-- needs the ID of the user to send them to. Reminders set in a private message
+-- don't have a channel, so channel_id can now be NULL.
+CREATE TABLE reminders_private (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    channel_id TEXT,
+    target_user TEXT NOT NULL,
+    target_user_id TEXT,
+    private BOOLEAN NOT NULL DEFAULT FALSE,
+    message TEXT NOT NULL,
+    remind_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    created_by TEXT NOT NULL,
+    recurrence TEXT,
+    timezone TEXT NOT NULL DEFAULT 'UTC'
+);
+
+INSERT INTO reminders_private (id, channel_id, target_user, message, remind_at, created_at, created_by, recurrence, timezone)
+SELECT id, channel_id, target_user, message, remind_at, created_at, created_by, recurrence, timezone FROM reminders;
+
+DROP TABLE reminders;
+
+ALTER TABLE reminders_private RENAME TO reminders;
+
+CREATE INDEX IF NOT EXISTS idx_reminders_remind_at ON reminders(remind_at);
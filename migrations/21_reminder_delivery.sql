@@ -0,0 +1,11 @@
+-- Reminders move through pending -> delivering -> delivered, or to failed
+-- once they run out of attempts. Claiming a reminder by moving it to
+-- delivering before it's sent means it can never be sent twice, even if the
+-- bot restarts part way through.
+ALTER TABLE reminders ADD COLUMN state TEXT NOT NULL DEFAULT 'pending'
+    CHECK (state IN ('pending', 'delivering', 'delivered', 'failed'));
+ALTER TABLE reminders ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE reminders ADD COLUMN last_error TEXT;
+ALTER TABLE reminders ADD COLUMN delivered_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_reminders_state ON reminders(state);
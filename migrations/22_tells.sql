@@ -0,0 +1,13 @@
+-- Messages left with tell, which are delivered the next time the recipient
+-- says something. Nicks are matched case-insensitively.
+CREATE TABLE IF NOT EXISTS tells (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recipient TEXT NOT NULL COLLATE NOCASE,
+    sender TEXT NOT NULL COLLATE NOCASE,
+    channel_id TEXT,
+    message TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_tells_recipient ON tells(recipient);
+CREATE INDEX IF NOT EXISTS idx_tells_sender ON tells(sender);
@@ -0,0 +1,3 @@
+-- Nicks can be taken by anyone, so delete rights go by the grabber's user
+-- ID. This is NULL for quotes grabbed before it was recorded.
+ALTER TABLE quotes ADD COLUMN grabber_id TEXT;
@@ -0,0 +1,3 @@
+-- Speakers' user IDs, so they can be recognized even if someone else takes
+-- their nick. This is NULL for quotes grabbed before it was recorded.
+ALTER TABLE quote_speakers ADD COLUMN user_id TEXT;
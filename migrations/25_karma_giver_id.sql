@@ -0,0 +1,5 @@
+-- Nicks can be changed at will, so cooldowns and budgets go by the giver's
+-- user ID. This is NULL for changes made before it was recorded.
+ALTER TABLE karma_events ADD COLUMN giver_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_karma_events_giver_id ON karma_events(giver_id);
@@ -0,0 +1,4 @@
+-- Nicks can be taken by anyone, so listing and cancelling reminders goes by
+-- the user ID of whoever set them. This is NULL for reminders set before it
+-- was recorded.
+ALTER TABLE reminders ADD COLUMN created_by_id TEXT;
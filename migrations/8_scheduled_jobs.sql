@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS scheduled_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plugin TEXT NOT NULL,
+    key TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    schedule TEXT,
+    run_at INTEGER NOT NULL,
+    locked_until INTEGER,
+    UNIQUE(plugin, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_jobs_run_at ON scheduled_jobs(run_at);
+
+-- Reminders used to be polled directly, so make sure any which are still
+-- pending get a job.
+INSERT INTO scheduled_jobs (plugin, key, payload, run_at)
+SELECT 'remind', 'reminder:' || id, CAST(id AS TEXT), remind_at FROM reminders;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS plugin_storage (
+    plugin TEXT NOT NULL,
+    channel_id TEXT NOT NULL DEFAULT '',
+    user TEXT NOT NULL DEFAULT '',
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    expires_at INTEGER,
+    PRIMARY KEY (plugin, channel_id, user, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_plugin_storage_expires_at ON plugin_storage(expires_at);
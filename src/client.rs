@@ -2,14 +2,40 @@ use std::collections::BTreeSet;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use chrono_tz::Tz;
 use futures::future::{select_all, FutureExt};
 use seabird::proto::seabird::{
-    BackendInfoRequest, BackendInfoResponse, CommandsRequest, CommandsResponse, CoreInfoRequest,
-    CoreInfoResponse, ListBackendsRequest, ListBackendsResponse,
+    seabird_client::SeabirdClient, BackendInfoRequest, BackendInfoResponse, CommandsRequest,
+    CommandsResponse, CoreInfoRequest, CoreInfoResponse, ListBackendsRequest, ListBackendsResponse,
 };
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::broadcast;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Uri};
 
 use crate::prelude::*;
+use crate::scheduler::Scheduler;
+use crate::storage::Storage;
+
+type InnerClient = SeabirdClient<InterceptedService<Channel, AuthHeaderInterceptor>>;
+
+// This mirrors the interceptor in the seabird crate, but that one isn't Clone,
+// which we need to be able to hand out a copy of the client for each call.
+#[derive(Clone, Debug)]
+struct AuthHeaderInterceptor {
+    auth_header: MetadataValue<Ascii>,
+}
+
+impl tonic::service::Interceptor for AuthHeaderInterceptor {
+    fn call(
+        &mut self,
+        mut req: tonic::Request<()>,
+    ) -> std::result::Result<tonic::Request<()>, tonic::Status> {
+        req.metadata_mut()
+            .insert("authorization", self.auth_header.clone());
+        Ok(req)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct ClientConfig {
@@ -17,6 +43,12 @@ pub struct ClientConfig {
     pub enabled_plugins: BTreeSet<String>,
     pub disabled_plugins: BTreeSet<String>,
 
+    // User IDs which are allowed to run admin commands.
+    pub admins: BTreeSet<String>,
+
+    // The timezone times are read and shown in.
+    pub default_timezone: Tz,
+
     pub db_url: String,
     pub db_pool_size: u32,
 }
@@ -29,6 +61,7 @@ impl ClientConfig {
         db_pool_size: u32,
         enabled_plugins: BTreeSet<String>,
         disabled_plugins: BTreeSet<String>,
+        admins: BTreeSet<String>,
     ) -> Self {
         ClientConfig {
             inner: seabird::ClientConfig { url, token },
@@ -36,6 +69,8 @@ impl ClientConfig {
             db_pool_size,
             enabled_plugins,
             disabled_plugins,
+            admins,
+            default_timezone: Tz::UTC,
         }
     }
 }
@@ -63,21 +98,31 @@ impl ClientConfig {
 #[derive(Debug)]
 pub struct Client {
     config: ClientConfig,
-    inner: Mutex<seabird::Client>,
+    inner: InnerClient,
     db_pool: sqlx::SqlitePool,
+    scheduler: Scheduler,
     broadcast: broadcast::Sender<Arc<Context>>,
 }
 
 impl Client {
+    // Cloning the tonic client is cheap and all clones share the same
+    // underlying channel, which multiplexes requests, so rather than
+    // serializing every call on a lock, each call gets its own handle.
+    fn inner(&self) -> InnerClient {
+        self.inner.clone()
+    }
+
     pub async fn perform_action(
         &self,
         channel_id: impl Into<String>,
         text: impl Into<String>,
     ) -> Result<()> {
-        self.inner
-            .lock()
-            .await
-            .perform_action(channel_id, text, None)
+        self.inner()
+            .perform_action(proto::PerformActionRequest {
+                channel_id: channel_id.into(),
+                text: text.into(),
+                tags: HashMap::new(),
+            })
             .await?;
         Ok(())
     }
@@ -87,10 +132,12 @@ impl Client {
         user_id: impl Into<String>,
         text: impl Into<String>,
     ) -> Result<()> {
-        self.inner
-            .lock()
-            .await
-            .perform_private_action(user_id, text, None)
+        self.inner()
+            .perform_private_action(proto::PerformPrivateActionRequest {
+                user_id: user_id.into(),
+                text: text.into(),
+                tags: HashMap::new(),
+            })
             .await?;
         Ok(())
     }
@@ -100,10 +147,12 @@ impl Client {
         channel_id: impl Into<String>,
         text: impl Into<String>,
     ) -> Result<()> {
-        self.inner
-            .lock()
-            .await
-            .send_message(channel_id, text, None)
+        self.inner()
+            .send_message(proto::SendMessageRequest {
+                channel_id: channel_id.into(),
+                text: text.into(),
+                tags: HashMap::new(),
+            })
             .await?;
         Ok(())
     }
@@ -113,20 +162,19 @@ impl Client {
         user_id: impl Into<String>,
         text: impl Into<String>,
     ) -> Result<()> {
-        self.inner
-            .lock()
-            .await
-            .send_private_message(user_id, text, None)
+        self.inner()
+            .send_private_message(proto::SendPrivateMessageRequest {
+                user_id: user_id.into(),
+                text: text.into(),
+                tags: HashMap::new(),
+            })
             .await?;
         Ok(())
     }
 
     pub async fn list_backends(&self) -> Result<ListBackendsResponse> {
         Ok(self
-            .inner
-            .lock()
-            .await
-            .inner_mut_ref()
+            .inner()
             .list_backends(ListBackendsRequest {})
             .await?
             .into_inner())
@@ -134,10 +182,7 @@ impl Client {
 
     pub async fn get_core_info(&self) -> Result<CoreInfoResponse> {
         Ok(self
-            .inner
-            .lock()
-            .await
-            .inner_mut_ref()
+            .inner()
             .get_core_info(CoreInfoRequest {})
             .await?
             .into_inner())
@@ -145,10 +190,7 @@ impl Client {
 
     pub async fn get_backend_info(&self, backend_id: String) -> Result<BackendInfoResponse> {
         Ok(self
-            .inner
-            .lock()
-            .await
-            .inner_mut_ref()
+            .inner()
             .get_backend_info(BackendInfoRequest { backend_id })
             .await?
             .into_inner())
@@ -156,10 +198,7 @@ impl Client {
 
     pub async fn registered_commands(&self) -> Result<CommandsResponse> {
         Ok(self
-            .inner
-            .lock()
-            .await
-            .inner_mut_ref()
+            .inner()
             .registered_commands(CommandsRequest {})
             .await?
             .into_inner())
@@ -172,30 +211,88 @@ impl Client {
     pub fn get_config(&self) -> &ClientConfig {
         &self.config
     }
-}
 
-impl Client {
-    pub async fn new(config: ClientConfig) -> Result<Self> {
-        let db_options = sqlx::sqlite::SqliteConnectOptions::from_str(&config.db_url)
-            .with_context(|| format!("Invalid $DATABASE_URL: {}", config.db_url))?
-            .create_if_missing(true);
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
 
-        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
-            .max_connections(config.db_pool_size)
-            .connect_with(db_options)
+    pub fn storage(&self, plugin: &str) -> Storage {
+        Storage::new(self.get_db(), plugin)
+    }
+
+    /// The timezone a user has chosen with the tz command, if any.
+    pub async fn user_timezone(&self, user_id: &str) -> Result<Option<Tz>> {
+        let name: Option<String> = self
+            .storage("timezone")
+            .user(user_id)
+            .get("timezone")
+            .await?;
+
+        name.map(|name| {
+            name.parse()
+                .map_err(|e| format_err!("invalid timezone for {}: {}", user_id, e))
+        })
+        .transpose()
+    }
+
+    /// The timezone chosen by whoever last set one under this nick, so
+    /// people can look each other up by name.
+    pub async fn nick_timezone(&self, nick: &str) -> Result<Option<Tz>> {
+        let user_id: Option<String> = self
+            .storage("timezone")
+            .get(&format!("nick:{}", nick.to_lowercase()))
+            .await?;
+
+        match user_id {
+            Some(user_id) => self.user_timezone(&user_id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Set or clear the timezone a user's times are read and shown in. The
+    /// nick they're using is remembered so they can be looked up by it.
+    pub async fn set_user_timezone(
+        &self,
+        user_id: &str,
+        nick: &str,
+        timezone: Option<Tz>,
+    ) -> Result<()> {
+        let storage = self.storage("timezone");
+        storage
+            .set(&format!("nick:{}", nick.to_lowercase()), &user_id)
             .await?;
 
-        crate::migrations::run(&db_pool).await?;
+        let storage = storage.user(user_id);
+        match timezone {
+            Some(timezone) => storage.set("timezone", &timezone.name()).await,
+            None => storage.delete("timezone").await.map(|_| ()),
+        }
+    }
 
-        let seabird_client = seabird::Client::new(config.inner.clone()).await?;
+    /// The timezone a user's times are read and shown in, which is
+    /// $SEABIRD_DEFAULT_TIMEZONE unless they've chosen one.
+    pub async fn timezone_for(&self, user_id: &str) -> Result<Tz> {
+        Ok(self
+            .user_timezone(user_id)
+            .await?
+            .unwrap_or(self.config.default_timezone))
+    }
+}
+
+impl Client {
+    pub async fn new(config: ClientConfig) -> Result<Self> {
+        let db_pool = connect_db(&config.db_url, config.db_pool_size).await?;
+
+        let seabird_client = connect(&config.inner).await?;
 
         let (sender, _) = broadcast::channel(100);
 
         Ok(Client {
             config,
             broadcast: sender,
+            scheduler: Scheduler::new(db_pool.clone()),
             db_pool,
-            inner: Mutex::new(seabird_client),
+            inner: seabird_client,
         })
     }
 
@@ -204,10 +301,7 @@ impl Client {
         commands: HashMap<String, crate::plugin::CommandMetadata>,
     ) -> Result<()> {
         let mut stream = self
-            .inner
-            .lock()
-            .await
-            .inner_mut_ref()
+            .inner()
             .stream_events(proto::StreamEventsRequest { commands })
             .await?
             .into_inner();
@@ -265,6 +359,10 @@ impl Client {
                 task??;
                 anyhow::bail!("A plugin task exited early");
             },
+            scheduler_res = client.scheduler.run().fuse() => {
+                scheduler_res?;
+                anyhow::bail!("Scheduler exited early");
+            },
         );
     }
 
@@ -273,6 +371,46 @@ impl Client {
     }
 }
 
+/// Open the database, creating it if needed, and run any pending migrations.
+pub async fn connect_db(db_url: &str, pool_size: u32) -> Result<sqlx::SqlitePool> {
+    let db_options = sqlx::sqlite::SqliteConnectOptions::from_str(db_url)
+        .with_context(|| format!("Invalid $DATABASE_URL: {}", db_url))?
+        .create_if_missing(true);
+
+    let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(pool_size)
+        .connect_with(db_options)
+        .await?;
+
+    crate::migrations::run(&db_pool).await?;
+
+    Ok(db_pool)
+}
+
+async fn connect(config: &seabird::ClientConfig) -> Result<InnerClient> {
+    let uri: Uri = config.url.parse().context("failed to parse seabird URL")?;
+    let mut channel_builder = Channel::builder(uri.clone());
+
+    match uri.scheme_str() {
+        None | Some("https") => {
+            channel_builder = channel_builder.tls_config(ClientTlsConfig::new())?;
+        }
+        _ => {}
+    }
+
+    let channel = channel_builder
+        .connect()
+        .await
+        .context("Failed to connect to seabird")?;
+
+    let auth_header: MetadataValue<Ascii> = format!("Bearer {}", config.token).parse()?;
+
+    Ok(SeabirdClient::with_interceptor(
+        channel,
+        AuthHeaderInterceptor { auth_header },
+    ))
+}
+
 #[derive(Clone, Debug)]
 pub struct Context {
     pub raw_event: SeabirdEvent,
@@ -328,6 +466,53 @@ impl Context {
         }
     }
 
+    /// The backend-specific ID of the user who sent this event, if any.
+    pub fn sender_id(&self) -> Option<&str> {
+        match &self.raw_event {
+            SeabirdEvent::Action(message) => message
+                .source
+                .as_ref()
+                .and_then(|s| s.user.as_ref().map(|u| u.id.as_str())),
+            SeabirdEvent::Message(message) => message
+                .source
+                .as_ref()
+                .and_then(|s| s.user.as_ref().map(|u| u.id.as_str())),
+            SeabirdEvent::Command(message) => message
+                .source
+                .as_ref()
+                .and_then(|s| s.user.as_ref().map(|u| u.id.as_str())),
+            SeabirdEvent::Mention(message) => message
+                .source
+                .as_ref()
+                .and_then(|s| s.user.as_ref().map(|u| u.id.as_str())),
+
+            SeabirdEvent::PrivateAction(message) => message.source.as_ref().map(|u| u.id.as_str()),
+            SeabirdEvent::PrivateMessage(message) => message.source.as_ref().map(|u| u.id.as_str()),
+
+            // Seabird-sent events don't carry a user ID.
+            SeabirdEvent::SendMessage(_)
+            | SeabirdEvent::SendPrivateMessage(_)
+            | SeabirdEvent::PerformAction(_)
+            | SeabirdEvent::PerformPrivateAction(_) => None,
+        }
+    }
+
+    /// Whether the sender of this event is listed in $SEABIRD_ADMINS.
+    pub fn is_admin(&self) -> bool {
+        self.sender_id()
+            .map(|id| self.client.get_config().admins.contains(id))
+            .unwrap_or(false)
+    }
+
+    /// The timezone times from the sender of this event should be read and
+    /// shown in.
+    pub async fn timezone(&self) -> Result<Tz> {
+        match self.sender_id() {
+            Some(user_id) => self.client.timezone_for(user_id).await,
+            None => Ok(self.default_timezone()),
+        }
+    }
+
     pub fn target_channel_id(&self) -> Option<&str> {
         match &self.raw_event {
             SeabirdEvent::Action(message) => message.source.as_ref().map(|s| s.channel_id.as_ref()),
@@ -546,20 +731,55 @@ impl Context {
     pub fn get_db(&self) -> sqlx::SqlitePool {
         self.client.get_db()
     }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        self.client.scheduler()
+    }
+
+    pub fn storage(&self, plugin: &str) -> Storage {
+        self.client.storage(plugin)
+    }
+
+    pub fn default_timezone(&self) -> Tz {
+        self.client.get_config().default_timezone
+    }
+
+    pub async fn user_timezone(&self, user_id: &str) -> Result<Option<Tz>> {
+        self.client.user_timezone(user_id).await
+    }
+
+    pub async fn nick_timezone(&self, nick: &str) -> Result<Option<Tz>> {
+        self.client.nick_timezone(nick).await
+    }
+
+    pub async fn set_user_timezone(
+        &self,
+        user_id: &str,
+        nick: &str,
+        timezone: Option<Tz>,
+    ) -> Result<()> {
+        self.client.set_user_timezone(user_id, nick, timezone).await
+    }
 }
 
 #[non_exhaustive]
 pub enum Event<'a> {
     // PRIVMSG target :msg
     Message(&'a str, &'a str),
+    #[allow(dead_code)]
     PrivateMessage(&'a str, &'a str),
 
+    // PRIVMSG target :\x01ACTION msg\x01
+    #[allow(dead_code)]
+    Action(&'a str, &'a str),
+
     // PRIVMSG somewhere :!command arg
     Command(&'a str, Option<&'a str>),
 
     // PRIVMSG somewhere :seabird: arg
     Mention(&'a str),
 
+    #[allow(dead_code)]
     Unknown(&'a SeabirdEvent),
 }
 
@@ -578,6 +798,11 @@ impl<'a> TryFrom<&'a Context> for Event<'a> {
                     .ok_or_else(|| format_err!("event missing sender"))?,
                 msg.text.as_str(),
             ),
+            SeabirdEvent::Action(msg) => Event::Action(
+                ctx.sender()
+                    .ok_or_else(|| format_err!("event missing sender"))?,
+                msg.text.as_str(),
+            ),
             SeabirdEvent::Command(msg) => {
                 let inner = msg.arg.trim();
                 Event::Command(
@@ -592,3 +817,73 @@ impl<'a> TryFrom<&'a Context> for Event<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::testing::FakeSeabird;
+
+    #[tokio::test]
+    async fn test_concurrent_send_throughput() -> Result<()> {
+        const MESSAGES: u32 = 200;
+        const LATENCY: Duration = Duration::from_millis(20);
+
+        let core = FakeSeabird::spawn(LATENCY).await?;
+        let client = Arc::new(core.client().await?);
+
+        let start = Instant::now();
+        futures::future::try_join_all((0..MESSAGES).map(|i| {
+            let client = client.clone();
+            async move { client.send_message("#bench", format!("reply {}", i)).await }
+        }))
+        .await?;
+        let elapsed = start.elapsed();
+
+        assert_eq!(core.sent_messages().len(), MESSAGES as usize);
+
+        // If calls were serialized this would take MESSAGES * LATENCY (4s).
+        // Leave plenty of headroom so slow CI machines don't flake.
+        assert!(
+            elapsed < LATENCY * 20,
+            "replies appear to be serialized: took {:?}",
+            elapsed
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_timezone() -> Result<()> {
+        let core = FakeSeabird::spawn(Duration::ZERO).await?;
+        let client = core.client().await?;
+
+        let chicago = chrono_tz::America::Chicago;
+        client
+            .set_user_timezone("U1", "Alice", Some(chicago))
+            .await?;
+
+        assert_eq!(client.user_timezone("U1").await?, Some(chicago));
+        assert_eq!(client.nick_timezone("alice").await?, Some(chicago));
+        assert_eq!(client.timezone_for("U2").await?, Tz::UTC);
+
+        // The setting follows the user ID, so taking over a nick doesn't
+        // take over whoever had it before.
+        client
+            .set_user_timezone("U2", "alice", Some(chrono_tz::Europe::Berlin))
+            .await?;
+        assert_eq!(client.user_timezone("U1").await?, Some(chicago));
+        assert_eq!(
+            client.nick_timezone("ALICE").await?,
+            Some(chrono_tz::Europe::Berlin)
+        );
+
+        client.set_user_timezone("U1", "alice", None).await?;
+        assert_eq!(client.user_timezone("U1").await?, None);
+        assert_eq!(client.nick_timezone("alice").await?, None);
+        assert_eq!(client.nick_timezone("bob").await?, None);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,6 @@
 #![warn(missing_debug_implementations, rust_2018_idioms)]
 
 use anyhow::Context;
-use std::fmt::Write;
 
 #[macro_use]
 extern crate log;
@@ -12,6 +11,10 @@ mod migrations;
 mod plugin;
 mod plugins;
 mod prelude;
+mod scheduler;
+mod storage;
+#[cfg(test)]
+mod testing;
 pub(crate) mod utils;
 
 pub use seabird::proto;
@@ -49,8 +52,25 @@ async fn main() -> error::Result<()> {
         info!("Loaded env from {:?}", path);
     }
 
+    // Maintenance subcommands only need the database, not a seabird
+    // connection.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some((command, rest)) = args.split_first() {
+        let db = client::connect_db(
+            &dotenvy::var("DATABASE_URL")
+                .context("Missing $DATABASE_URL. You must specify a sqlite URL.")?,
+            1,
+        )
+        .await?;
+
+        return match command.as_str() {
+            "karma" => plugins::karma_transfer::run_cli(&db, rest).await,
+            _ => Err(anyhow::format_err!("unknown subcommand {}", command)),
+        };
+    }
+
     // Load our config from command line arguments
-    let config = client::ClientConfig::new(
+    let mut config = client::ClientConfig::new(
         dotenvy::var("SEABIRD_HOST")
             .context("Missing $SEABIRD_HOST. You must specify a Seabird host.")?,
         dotenvy::var("SEABIRD_TOKEN")
@@ -71,7 +91,14 @@ async fn main() -> error::Result<()> {
             .split_terminator(',')
             .map(|s| s.to_string())
             .collect(),
+        dotenvy::var("SEABIRD_ADMINS")
+            .unwrap_or_else(|_| "".to_string())
+            .split_terminator(',')
+            .map(|s| s.to_string())
+            .collect(),
     );
+    config.default_timezone =
+        utils::env_var_or("SEABIRD_DEFAULT_TIMEZONE", config.default_timezone)?;
 
     let client = client::Client::new(config).await?;
     client.run().await
@@ -1,7 +1,3 @@
-use std::path::Path;
-
-use sqlx::migrate::Migrator;
-
 use crate::prelude::*;
 
 pub async fn run(pool: &sqlx::SqlitePool) -> Result<()> {
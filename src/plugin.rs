@@ -62,6 +62,8 @@ pub async fn load(bot: Arc<Client>) -> Result<Vec<PluginMetadata>> {
         "remind",
         "riddle",
         "scryfall",
+        "tell",
+        "timezone",
         "introspection",
         "help",
     ];
@@ -173,5 +175,13 @@ pub async fn load(bot: Arc<Client>) -> Result<Vec<PluginMetadata>> {
         ret.push(start_plugin::<plugins::RemindPlugin>(&bot)?);
     }
 
+    if config.plugin_enabled("tell") {
+        ret.push(start_plugin::<plugins::TellPlugin>(&bot)?);
+    }
+
+    if config.plugin_enabled("timezone") {
+        ret.push(start_plugin::<plugins::TimezonePlugin>(&bot)?);
+    }
+
     Ok(ret)
 }
@@ -149,6 +149,7 @@ impl fmt::Display for TeaVariant {
 }
 
 #[derive(Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Temperature {
     // Cold
     Iced,
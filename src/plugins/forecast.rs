@@ -1,12 +1,10 @@
-use time::format_description::{modifier::Weekday, Component, FormatItem};
+use chrono::DateTime;
+use chrono_tz::Tz;
 
 use crate::utils::{maps, openweathermap};
 
 use crate::prelude::*;
 
-const WEEKDAY_FORMAT: FormatItem<'_> =
-    FormatItem::Component(Component::Weekday(Weekday::default()));
-
 pub struct ForecastPlugin {
     darksky: openweathermap::Client,
     maps: maps::Client,
@@ -123,9 +121,8 @@ impl ForecastPlugin {
         // errors.
         let mut results = Vec::new();
 
-        for day in res.into_iter().skip(1).take(3) {
-            let weekday = day.time.format(&WEEKDAY_FORMAT)?;
-
+        let now = chrono::Utc::now().with_timezone(&ctx.timezone().await?);
+        for (weekday, day) in upcoming_days(res, &now).into_iter().take(3) {
             let ret = ctx
                 .mention_reply(&format!(
                     "{}: High {:.2}°F, Low {:.2}°F, Humidity {:.0}%. {}.",
@@ -187,6 +184,24 @@ impl ForecastPlugin {
     }
 }
 
+/// The days of a forecast after today in now's timezone, along with the name
+/// of each day there, so days don't change at midnight UTC.
+fn upcoming_days(
+    forecast: openweathermap::Forecast,
+    now: &DateTime<Tz>,
+) -> Vec<(String, openweathermap::Weather)> {
+    let today = now.date_naive();
+
+    forecast
+        .into_iter()
+        .filter_map(|day| {
+            let local = DateTime::from_timestamp(day.time.unix_timestamp(), 0)?
+                .with_timezone(&now.timezone());
+            (local.date_naive() > today).then(|| (local.format("%A").to_string(), day))
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 enum LocationStatus {
     NoLocations,
@@ -196,6 +211,7 @@ enum LocationStatus {
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct ForecastLocation {
+    #[allow(dead_code)]
     pub nick: String,
     pub address: String,
     pub lat: f64,
@@ -278,6 +294,11 @@ impl Plugin for ForecastPlugin {
     }
 
     async fn run(self, bot: Arc<Client>) -> Result<()> {
+        if utils::cache::persistence_enabled()? {
+            self.darksky.persist_cache(bot.get_db());
+            self.maps.persist_cache(bot.get_db());
+        }
+
         let mut stream = bot.subscribe();
 
         while let Ok(ctx) = stream.recv().await {
@@ -293,3 +314,45 @@ impl Plugin for ForecastPlugin {
         Err(format_err!("forecast plugin lagged"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_upcoming_days() {
+        let day = |timestamp| openweathermap::Weather {
+            time: time::OffsetDateTime::from_unix_timestamp(timestamp).unwrap(),
+            temperature_high: 70.0,
+            temperature_low: 50.0,
+            humidity: 40,
+            summary: "clear sky".to_string(),
+        };
+
+        // 03:00 UTC from Saturday, October 17th 2026, which is still the
+        // evening before in Chicago.
+        let start = 1792206000;
+        let forecast = (0..4).map(|i| day(start + i * 86400)).collect();
+
+        // Friday evening in Chicago, but already Saturday in UTC.
+        let now = chrono_tz::America::Chicago
+            .with_ymd_and_hms(2026, 10, 16, 21, 0, 0)
+            .unwrap();
+        let days: Vec<String> = upcoming_days(forecast, &now)
+            .into_iter()
+            .map(|(weekday, _)| weekday)
+            .collect();
+
+        // The first day is still today in Chicago, so it's skipped.
+        assert_eq!(days, vec!["Saturday", "Sunday", "Monday"]);
+
+        let forecast = (0..4).map(|i| day(start + i * 86400)).collect();
+        let days: Vec<String> = upcoming_days(forecast, &now.with_timezone(&Tz::UTC))
+            .into_iter()
+            .map(|(weekday, _)| weekday)
+            .collect();
+        assert_eq!(days, vec!["Sunday", "Monday", "Tuesday"]);
+    }
+}
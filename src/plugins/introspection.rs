@@ -1,7 +1,8 @@
 use std::fmt::Write;
+use std::time::Instant;
 
 use git_version::git_version;
-use time::{Duration, Instant};
+use time::Duration;
 
 use crate::prelude::*;
 
@@ -45,7 +46,7 @@ impl IntrospectionPlugin {
     }
 
     async fn handle_uptime(&self, ctx: &Context) -> Result<()> {
-        let elapsed = self.started.elapsed();
+        let elapsed = Duration::try_from(self.started.elapsed())?;
 
         let resp = ctx.get_core_info().await?;
         println!("{} {}", resp.current_timestamp, resp.startup_timestamp);
@@ -92,6 +93,27 @@ impl IntrospectionPlugin {
         ctx.mention_reply(&lines.join(", ")).await
     }
 
+    async fn handle_cache_stats(&self, ctx: &Context) -> Result<()> {
+        let stats = utils::cache::stats();
+        if stats.is_empty() {
+            return ctx.mention_reply("No caches in use.").await;
+        }
+
+        let lines: Vec<_> = stats
+            .iter()
+            .map(|stats| {
+                format!(
+                    "{}: {} hits, {} misses",
+                    stats.source,
+                    stats.hits(),
+                    stats.misses()
+                )
+            })
+            .collect();
+
+        ctx.mention_reply(&lines.join(", ")).await
+    }
+
     async fn handle_version(&self, ctx: &Context) -> Result<()> {
         ctx.mention_reply(&format!(
             "seabird-plugin-bundle {}-{}",
@@ -126,6 +148,13 @@ impl Plugin for IntrospectionPlugin {
                         .to_string(),
                 full_help: "gets metadata for the given backend.".to_string(),
             },
+            CommandMetadata {
+                name: "cache_stats".to_string(),
+                short_help: "usage: cache_stats. gets hit and miss counts for API caches."
+                    .to_string(),
+                full_help: "gets hit and miss counts for the caches in front of upstream APIs."
+                    .to_string(),
+            },
             CommandMetadata {
                 name: "version".to_string(),
                 short_help: "usage: version. gets introspection plugin version.".to_string(),
@@ -147,6 +176,7 @@ impl Plugin for IntrospectionPlugin {
                 Ok(Event::Command("backend_metadata", Some(arg))) => {
                     self.handle_backend_metadata(&ctx, arg).await
                 }
+                Ok(Event::Command("cache_stats", _)) => self.handle_cache_stats(&ctx).await,
                 Ok(Event::Command("version", _)) => self.handle_version(&ctx).await,
                 _ => Ok(()),
             };
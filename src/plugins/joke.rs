@@ -8,7 +8,8 @@ const API_BASE: &str = "https://v2.jokeapi.dev/joke";
 const CATEGORIES: &[&str] = &["any", "misc", "programming", "pun", "spooky", "christmas"];
 
 pub struct JokePlugin {
-    client: reqwest::Client,
+    api_base: String,
+    client: utils::http::Client,
 }
 
 #[derive(Deserialize)]
@@ -23,13 +24,19 @@ struct JokeResponse {
 }
 
 impl JokePlugin {
+    pub fn with_api_base(api_base: String) -> Self {
+        JokePlugin {
+            api_base,
+            client: utils::http::client(),
+        }
+    }
+
     async fn fetch_joke(&self, category: &str) -> Result<JokeResponse> {
-        let url = format!("{}/{}?safe-mode", API_BASE, category);
+        let url = format!("{}/{}?safe-mode", self.api_base, category);
 
         let resp: JokeResponse = self
             .client
-            .get(&url)
-            .send()
+            .get(&url, &())
             .await?
             .error_for_status()?
             .json()
@@ -74,10 +81,7 @@ impl JokePlugin {
 #[async_trait]
 impl Plugin for JokePlugin {
     fn new_from_env() -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(5))
-            .build()?;
-        Ok(JokePlugin { client })
+        Ok(JokePlugin::with_api_base(API_BASE.to_string()))
     }
 
     fn command_metadata(&self) -> Vec<CommandMetadata> {
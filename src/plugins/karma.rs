@@ -5,144 +5,1508 @@ use lazy_static::lazy_static;
 use regex::Regex;
 
 use crate::prelude::*;
+use crate::scheduler::now;
+
+pub mod transfer;
 
 lazy_static! {
     static ref KARMA_RE: Regex =
         Regex::new(r#"([\w]{2,}|".+?")([+-]{2,})(?:\s|$)"#).expect("invalid karma regex");
+
+    // A reason starts with a "#" followed by whitespace, so channel names
+    // like "#rust" aren't mistaken for one.
+    static ref REASON_RE: Regex = Regex::new(r#"(?:^|\s)#\s+(.*\S)\s*$"#).expect("invalid reason regex");
+
+    // Inline code (including fenced blocks) and URLs, which can optionally
+    // be skipped when looking for karma changes.
+    static ref IGNORED_RE: Regex =
+        Regex::new(r#"```[\s\S]*?```|`[^`]*`|\b[a-zA-Z][a-zA-Z0-9+.-]*://\S+"#).expect("invalid ignored regex");
 }
 
+const DEFAULT_LEADERBOARD_SIZE: i64 = 5;
+const MAX_LEADERBOARD_SIZE: i64 = 10;
+const HISTORY_SIZE: i64 = 5;
+
 #[derive(sqlx::FromRow, Debug)]
 pub struct Karma {
     pub name: String,
     pub score: i64,
 }
 
-impl Karma {
-    async fn sanitize_name(conn: &sqlx::SqlitePool, name: &str) -> Result<String> {
-        let name = name.to_lowercase();
+/// Where a karma change came from.
+#[derive(Debug, Default)]
+struct ChangeSource<'a> {
+    scope: &'a str,
+    giver: Option<&'a str>,
+    giver_id: Option<&'a str>,
+    channel_id: Option<&'a str>,
+    reason: Option<&'a str>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct KarmaEvent {
+    name: String,
+    delta: i64,
+    giver: Option<String>,
+    reason: Option<String>,
+}
+
+impl KarmaEvent {
+    /// Get the most recent changes to a name which came with a reason.
+    async fn recent_reasons(
+        conn: &sqlx::SqlitePool,
+        scope: &str,
+        name: &str,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            KarmaEvent,
+            "SELECT name, delta, giver, reason FROM karma_events
+WHERE scope=$1 AND name=$2 AND reason IS NOT NULL
+ORDER BY created_at DESC, id DESC LIMIT $3;",
+            scope,
+            name,
+            limit
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Get the most recent changes made by a user, along with how many
+    /// changes they have made in total.
+    async fn recent_given(
+        conn: &sqlx::SqlitePool,
+        giver: &str,
+        limit: i64,
+    ) -> Result<(Vec<Self>, i64)> {
+        let events = sqlx::query_as!(
+            KarmaEvent,
+            "SELECT name, delta, giver, reason FROM karma_events
+WHERE giver=$1 COLLATE NOCASE
+ORDER BY created_at DESC, id DESC LIMIT $2;",
+            giver,
+            limit
+        )
+        .fetch_all(conn)
+        .await?;
+
+        let total = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM karma_events WHERE giver=$1 COLLATE NOCASE;"#,
+            giver
+        )
+        .fetch_one(conn)
+        .await?
+        .count;
+
+        Ok((events, total))
+    }
+
+    /// When a giver last changed a name's karma, if ever.
+    async fn last_given(
+        conn: &sqlx::SqlitePool,
+        giver_id: &str,
+        name: &str,
+    ) -> Result<Option<i64>> {
+        Ok(sqlx::query!(
+            r#"SELECT MAX(created_at) as "created_at: i64" FROM karma_events
+WHERE giver_id=$1 AND name=$2;"#,
+            giver_id,
+            name
+        )
+        .fetch_one(conn)
+        .await?
+        .created_at)
+    }
+
+    /// How much karma a giver has handed out since the given time, counting
+    /// decrements as well as increments.
+    async fn total_given_since(conn: &sqlx::SqlitePool, giver_id: &str, since: i64) -> Result<i64> {
+        Ok(sqlx::query!(
+            r#"SELECT COALESCE(SUM(ABS(delta)), 0) as "total!: i64" FROM karma_events
+WHERE giver_id=$1 AND created_at >= $2;"#,
+            giver_id,
+            since
+        )
+        .fetch_one(conn)
+        .await?
+        .total)
+    }
+
+    /// Revert every change a giver made since the given time, returning the
+    /// names and deltas which were undone.
+    async fn undo_since(
+        conn: &sqlx::SqlitePool,
+        giver: &str,
+        since: i64,
+    ) -> Result<Vec<(String, i64)>> {
+        let mut tx = conn.begin().await?;
+
+        let events = sqlx::query!(
+            r#"SELECT id as "id!", scope, name, delta FROM karma_events
+WHERE giver=$1 COLLATE NOCASE AND created_at >= $2
+ORDER BY id;"#,
+            giver,
+            since
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut undone = Vec::new();
+
+        for event in events {
+            sqlx::query!(
+                "UPDATE karma SET score=score-$1 WHERE scope=$2 AND name=$3;",
+                event.delta,
+                event.scope,
+                event.name
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!("DELETE FROM karma_events WHERE id=$1;", event.id)
+                .execute(&mut *tx)
+                .await?;
+
+            undone.push((event.name, event.delta));
+        }
+
+        tx.commit().await?;
+
+        Ok(undone)
+    }
+}
+
+fn format_delta(delta: i64) -> String {
+    if delta > 0 {
+        format!("+{}", delta)
+    } else {
+        delta.to_string()
+    }
+}
+
+/// Blank out inline code and URLs so karma-like text inside them, like the
+/// "count--" in "`while (count--) {}`", isn't counted.
+fn strip_ignored(msg: &str) -> std::borrow::Cow<'_, str> {
+    IGNORED_RE.replace_all(msg, " ")
+}
+
+/// Split a trailing reason, like the "for fixing the build" in "rust++ #
+/// for fixing the build", off of a message.
+fn split_reason(msg: &str) -> (&str, Option<&str>) {
+    match REASON_RE.captures(msg) {
+        Some(captures) => {
+            let start = captures.get(0).map(|m| m.start()).unwrap_or_default();
+            (&msg[..start], captures.get(1).map(|m| m.as_str()))
+        }
+        None => (msg, None),
+    }
+}
+
+impl Karma {
+    async fn sanitize_name(conn: &sqlx::SqlitePool, name: &str) -> Result<String> {
+        let name = name.to_lowercase();
+
+        Ok(
+            sqlx::query!("SELECT target FROM karma_alias WHERE name=$1;", name)
+                .map(|row| row.target)
+                .fetch_optional(conn)
+                .await?
+                .unwrap_or_else(|| name.to_string()),
+        )
+    }
+
+    async fn get_by_name(conn: &sqlx::SqlitePool, scope: &str, name: &str) -> Result<Self> {
+        Ok(sqlx::query_as!(
+            Karma,
+            "SELECT name, score FROM karma WHERE scope=$1 AND name=$2;",
+            scope,
+            name
+        )
+        .fetch_optional(conn)
+        .await?
+        .unwrap_or_else(|| Karma {
+            name: name.to_string(),
+            score: 0,
+        }))
+    }
+
+    /// Get a name's karma summed across every scope.
+    async fn get_global(conn: &sqlx::SqlitePool, name: &str) -> Result<Self> {
+        let score = sqlx::query!(
+            r#"SELECT COALESCE(SUM(score), 0) as "score!: i64" FROM karma WHERE name=$1;"#,
+            name
+        )
+        .fetch_one(conn)
+        .await?
+        .score;
+
+        Ok(Karma {
+            name: name.to_string(),
+            score,
+        })
+    }
+
+    async fn create_or_update(
+        conn: &sqlx::SqlitePool,
+        name: &str,
+        score: i32,
+        source: &ChangeSource<'_>,
+    ) -> Result<Self> {
+        let now = now();
+
+        let mut tx = conn.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO karma (scope, name, score) VALUES ($1, $2, $3)
+ON CONFLICT (scope, name) DO UPDATE SET score=EXCLUDED.score+karma.score;",
+            source.scope,
+            name,
+            score
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO karma_events (scope, name, delta, created_at, giver, giver_id, channel_id, reason) VALUES ($1, $2, $3, $4, $5, $6, $7, $8);",
+            source.scope,
+            name,
+            score,
+            now,
+            source.giver,
+            source.giver_id,
+            source.channel_id,
+            source.reason,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Karma::get_by_name(conn, source.scope, name).await
+    }
+
+    /// Get the highest (or lowest, if ascending) scores. If a start time is
+    /// given, only changes made since then are counted.
+    async fn leaderboard(
+        conn: &sqlx::SqlitePool,
+        scope: &str,
+        limit: i64,
+        ascending: bool,
+        since: Option<i64>,
+    ) -> Result<Vec<Self>> {
+        let rows = match (since, ascending) {
+            (None, false) => {
+                sqlx::query_as!(
+                    Karma,
+                    "SELECT name, score FROM karma WHERE scope=$1 ORDER BY score DESC, name ASC LIMIT $2;",
+                    scope,
+                    limit
+                )
+                .fetch_all(conn)
+                .await?
+            }
+            (None, true) => {
+                sqlx::query_as!(
+                    Karma,
+                    "SELECT name, score FROM karma WHERE scope=$1 ORDER BY score ASC, name ASC LIMIT $2;",
+                    scope,
+                    limit
+                )
+                .fetch_all(conn)
+                .await?
+            }
+            (Some(since), false) => sqlx::query_as!(
+                Karma,
+                r#"SELECT name, SUM(delta) as "score!: i64" FROM karma_events WHERE scope=$1 AND created_at >= $2
+GROUP BY name ORDER BY 2 DESC, name ASC LIMIT $3;"#,
+                scope,
+                since,
+                limit
+            )
+            .fetch_all(conn)
+            .await?,
+            (Some(since), true) => sqlx::query_as!(
+                Karma,
+                r#"SELECT name, SUM(delta) as "score!: i64" FROM karma_events WHERE scope=$1 AND created_at >= $2
+GROUP BY name ORDER BY 2 ASC, name ASC LIMIT $3;"#,
+                scope,
+                since,
+                limit
+            )
+            .fetch_all(conn)
+            .await?,
+        };
+
+        Ok(rows)
+    }
+
+    /// Find where a name places among all entries. Returns the 1-based rank
+    /// and the number of entries, or None if the name has never had karma.
+    /// Tied scores share a rank.
+    async fn rank(conn: &sqlx::SqlitePool, scope: &str, name: &str) -> Result<Option<(i64, i64)>> {
+        let row = sqlx::query!(
+            r#"SELECT
+    (SELECT COUNT(*) FROM karma AS other WHERE other.scope=karma.scope AND other.score > karma.score) + 1 as "rank!: i64",
+    (SELECT COUNT(*) FROM karma AS other WHERE other.scope=karma.scope) as "total!: i64"
+FROM karma WHERE scope=$1 AND name=$2;"#,
+            scope,
+            name
+        )
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(row.map(|row| (row.rank, row.total)))
+    }
+
+    /// Make name an alias of target, returning the name it now points to.
+    ///
+    /// Aliases are only ever followed once, so they're kept flat: the target
+    /// is resolved first and anything which pointed at name is moved over to
+    /// the new target.
+    async fn add_alias(conn: &sqlx::SqlitePool, name: &str, target: &str) -> Result<String> {
+        let mut tx = conn.begin().await?;
+        let target = add_alias_tx(&mut tx, name, target).await?;
+        tx.commit().await?;
+
+        Ok(target)
+    }
+
+    /// Remove an alias. Returns false if it didn't exist.
+    async fn remove_alias(conn: &sqlx::SqlitePool, name: &str) -> Result<bool> {
+        let name = name.to_lowercase();
+
+        let result = sqlx::query!("DELETE FROM karma_alias WHERE name=$1;", name)
+            .execute(conn)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn aliases(conn: &sqlx::SqlitePool, target: &str) -> Result<Vec<String>> {
+        Ok(sqlx::query!(
+            "SELECT name FROM karma_alias WHERE target=$1 ORDER BY name;",
+            target
+        )
+        .map(|row| row.name)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Fold one name's score and history into another in every scope and
+    /// make it an alias of the other, returning the combined global karma.
+    async fn merge(conn: &sqlx::SqlitePool, from: &str, into: &str) -> Result<Self> {
+        let from = Karma::sanitize_name(conn, from).await?;
+        let into = Karma::sanitize_name(conn, into).await?;
+
+        if from == into {
+            return Err(format_err!("{} and {} are already the same", from, into));
+        }
+
+        let mut tx = conn.begin().await?;
+
+        sqlx::query!(
+            "INSERT INTO karma (scope, name, score) SELECT scope, $1, score FROM karma WHERE name=$2
+ON CONFLICT (scope, name) DO UPDATE SET score=EXCLUDED.score+karma.score;",
+            into,
+            from
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM karma WHERE name=$1;", from)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("UPDATE karma_events SET name=$1 WHERE name=$2;", into, from)
+            .execute(&mut *tx)
+            .await?;
+
+        add_alias_tx(&mut tx, &from, &into).await?;
+
+        tx.commit().await?;
+
+        Karma::get_global(conn, &into).await
+    }
+
+    /// Get every score in a scope with decay applied, highest first.
+    ///
+    /// Decay is worked out from the karma history of the current season.
+    async fn decayed(
+        conn: &sqlx::SqlitePool,
+        scope: &str,
+        decay: Decay,
+        now: i64,
+    ) -> Result<Vec<Self>> {
+        let season_start = Karma::season_start(conn).await?;
+
+        let mut scores: BTreeMap<String, (i64, Vec<(i64, i64)>)> =
+            sqlx::query!("SELECT name, score FROM karma WHERE scope=$1;", scope)
+                .map(|row| (row.name, (row.score, Vec::new())))
+                .fetch_all(conn)
+                .await?
+                .into_iter()
+                .collect();
+
+        let events = sqlx::query!(
+            "SELECT name, delta, created_at FROM karma_events WHERE scope=$1 AND created_at >= $2;",
+            scope,
+            season_start
+        )
+        .fetch_all(conn)
+        .await?;
+
+        for event in events {
+            if let Some((_, history)) = scores.get_mut(&event.name) {
+                history.push((event.delta, event.created_at));
+            }
+        }
+
+        let mut ret: Vec<_> = scores
+            .into_iter()
+            .map(|(name, (score, history))| Karma {
+                name,
+                score: decay_score(score, &history, decay, now),
+            })
+            .collect();
+        ret.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(ret)
+    }
+
+    /// Get a single name's score in a scope with decay applied.
+    async fn decayed_by_name(
+        conn: &sqlx::SqlitePool,
+        scope: &str,
+        name: &str,
+        decay: Decay,
+        now: i64,
+    ) -> Result<Self> {
+        let score = sqlx::query!(
+            "SELECT score FROM karma WHERE scope=$1 AND name=$2;",
+            scope,
+            name
+        )
+        .map(|row| row.score)
+        .fetch_optional(conn)
+        .await?;
+
+        let score = match score {
+            Some(score) => score,
+            None => {
+                return Ok(Karma {
+                    name: name.to_string(),
+                    score: 0,
+                })
+            }
+        };
+
+        let season_start = Karma::season_start(conn).await?;
+        let history = sqlx::query!(
+            "SELECT delta, created_at FROM karma_events WHERE scope=$1 AND name=$2 AND created_at >= $3;",
+            scope,
+            name,
+            season_start
+        )
+        .map(|row| (row.delta, row.created_at))
+        .fetch_all(conn)
+        .await?;
+
+        Ok(Karma {
+            name: name.to_string(),
+            score: decay_score(score, &history, decay, now),
+        })
+    }
+
+    /// When the current season started, or 0 if there haven't been any.
+    async fn season_start(conn: &sqlx::SqlitePool) -> Result<i64> {
+        Ok(sqlx::query!(
+            r#"SELECT COALESCE(MAX(ended_at), 0) as "ended_at!: i64" FROM karma_seasons;"#
+        )
+        .fetch_one(conn)
+        .await?
+        .ended_at)
+    }
+
+    /// Archive every current score under the given season name and start
+    /// over from zero. Returns how many scores were archived.
+    async fn end_season(conn: &sqlx::SqlitePool, season: &str) -> Result<u64> {
+        let now = now();
+
+        let mut tx = conn.begin().await?;
+
+        let existing = sqlx::query!(
+            "SELECT season FROM karma_seasons WHERE season=$1 LIMIT 1;",
+            season
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if existing.is_some() {
+            return Err(format_err!("there is already a season named {}", season));
+        }
+
+        let archived = sqlx::query!(
+            "INSERT INTO karma_seasons (season, scope, name, score, ended_at) SELECT $1, scope, name, score, $2 FROM karma;",
+            season,
+            now
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if archived == 0 {
+            return Err(format_err!("there isn't any karma to archive"));
+        }
+
+        sqlx::query!("DELETE FROM karma;").execute(&mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(archived)
+    }
+
+    /// List the names of past seasons, oldest first.
+    async fn seasons(conn: &sqlx::SqlitePool) -> Result<Vec<String>> {
+        Ok(sqlx::query!(
+            r#"SELECT season as "season!: String" FROM karma_seasons GROUP BY season ORDER BY MAX(ended_at), season;"#
+        )
+        .map(|row| row.season)
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Get the highest scores from a past season.
+    async fn season_leaderboard(
+        conn: &sqlx::SqlitePool,
+        season: &str,
+        scope: &str,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            Karma,
+            "SELECT name, score FROM karma_seasons WHERE season=$1 AND scope=$2 ORDER BY score DESC, name ASC LIMIT $3;",
+            season,
+            scope,
+            limit
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Get a name's score at the end of a past season, if it had one.
+    async fn season_score(
+        conn: &sqlx::SqlitePool,
+        season: &str,
+        scope: &str,
+        name: &str,
+    ) -> Result<Option<i64>> {
+        Ok(sqlx::query!(
+            "SELECT score FROM karma_seasons WHERE season=$1 AND scope=$2 AND name=$3;",
+            season,
+            scope,
+            name
+        )
+        .map(|row| row.score)
+        .fetch_optional(conn)
+        .await?)
+    }
+}
+
+/// How old karma loses value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Decay {
+    #[default]
+    None,
+
+    // Karma older than this many days counts for half.
+    Halve {
+        days: i64,
+    },
+
+    // Karma loses half its value every this many days.
+    Exponential {
+        half_life_days: f64,
+    },
+}
+
+impl Decay {
+    /// How much a change made age_secs ago counts for.
+    fn weight(self, age_secs: i64) -> f64 {
+        const DAY: f64 = 24.0 * 60.0 * 60.0;
+
+        match self {
+            Decay::None => 1.0,
+            Decay::Halve { days } => {
+                if age_secs > days.saturating_mul(DAY as i64) {
+                    0.5
+                } else {
+                    1.0
+                }
+            }
+            Decay::Exponential { half_life_days } => {
+                0.5f64.powf(age_secs.max(0) as f64 / DAY / half_life_days)
+            }
+        }
+    }
+}
+
+/// Apply decay to a score, given the (delta, created_at) history behind it.
+///
+/// Any part of the score without history, such as karma given before history
+/// was recorded, is at least as old as the earliest change, so it's aged from
+/// there. Scores with no history at all aren't decayed.
+fn decay_score(score: i64, history: &[(i64, i64)], decay: Decay, now: i64) -> i64 {
+    let mut unaccounted = score;
+    let mut decayed = 0.0;
+
+    for &(delta, created_at) in history {
+        unaccounted -= delta;
+        decayed += decay.weight(now - created_at) * delta as f64;
+    }
+
+    let unaccounted_weight = history
+        .iter()
+        .map(|&(_, created_at)| created_at)
+        .min()
+        .map_or(1.0, |oldest| decay.weight(now - oldest));
+
+    (decayed + unaccounted_weight * unaccounted as f64).round() as i64
+}
+
+impl std::str::FromStr for Decay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let usage = || format_err!("expected \"none\", \"halve:<days>\" or \"exponential:<days>\"");
+
+        match s.split_once(':') {
+            None if s == "none" => Ok(Decay::None),
+            Some(("halve", days)) => match days.parse() {
+                Ok(days) if days > 0 => Ok(Decay::Halve { days }),
+                _ => Err(usage()),
+            },
+            Some(("exponential", days)) => match days.parse() {
+                Ok(half_life_days) if half_life_days > 0.0 => {
+                    Ok(Decay::Exponential { half_life_days })
+                }
+                _ => Err(usage()),
+            },
+            _ => Err(usage()),
+        }
+    }
+}
+
+async fn add_alias_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    name: &str,
+    target: &str,
+) -> Result<String> {
+    let name = name.to_lowercase();
+    let target = target.to_lowercase();
+
+    let target = sqlx::query!("SELECT target FROM karma_alias WHERE name=$1;", target)
+        .map(|row| row.target)
+        .fetch_optional(&mut **tx)
+        .await?
+        .unwrap_or(target);
+
+    if name == target {
+        return Err(format_err!(
+            "aliasing {} to {} would create a cycle",
+            name,
+            target
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE karma_alias SET target=$1 WHERE target=$2;",
+        target,
+        name
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO karma_alias (name, target) VALUES ($1, $2)
+ON CONFLICT (name) DO UPDATE SET target=EXCLUDED.target;",
+        name,
+        target
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(target)
+}
+
+/// Parse "<name> -> <target>", or for convenience "<name> <target>" when
+/// neither contains spaces.
+fn parse_alias_args(arg: &str) -> Option<(&str, &str)> {
+    let (name, target) = match arg.split_once("->") {
+        Some(parts) => parts,
+        None => {
+            let mut words = arg.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some(name), Some(target), None) => (name, target),
+                _ => return None,
+            }
+        }
+    };
+
+    let name = name.trim().trim_matches('"');
+    let target = target.trim().trim_matches('"');
+
+    if name.is_empty() || target.is_empty() {
+        return None;
+    }
+
+    Some((name, target))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Window {
+    Day,
+    Week,
+    Month,
+}
+
+impl Window {
+    fn seconds(self) -> i64 {
+        match self {
+            Window::Day => 24 * 60 * 60,
+            Window::Week => 7 * 24 * 60 * 60,
+            Window::Month => 30 * 24 * 60 * 60,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Window::Day => "today",
+            Window::Week => "this week",
+            Window::Month => "this month",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct LeaderboardArgs {
+    limit: i64,
+    window: Option<Window>,
+}
+
+/// Parse the arguments to "karma top" and "karma bottom", which are an
+/// optional count and an optional time window in either order, like "5",
+/// "week" or "this month 3".
+fn parse_leaderboard_args(arg: Option<&str>) -> Result<LeaderboardArgs> {
+    let mut args = LeaderboardArgs {
+        limit: DEFAULT_LEADERBOARD_SIZE,
+        window: None,
+    };
+
+    for word in arg.unwrap_or_default().split_whitespace() {
+        match word.to_lowercase().as_str() {
+            "this" | "past" => {}
+            "day" | "today" => args.window = Some(Window::Day),
+            "week" => args.window = Some(Window::Week),
+            "month" => args.window = Some(Window::Month),
+            other => {
+                let limit: i64 = other
+                    .parse()
+                    .map_err(|_| format_err!("unknown leaderboard option \"{}\"", word))?;
+                if !(1..=MAX_LEADERBOARD_SIZE).contains(&limit) {
+                    return Err(format_err!(
+                        "leaderboards can show between 1 and {} entries",
+                        MAX_LEADERBOARD_SIZE
+                    ));
+                }
+                args.limit = limit;
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+pub struct KarmaPlugin {
+    config: KarmaConfig,
+}
+
+#[derive(Debug, Clone)]
+struct KarmaConfig {
+    // Whether users may give karma to themselves.
+    allow_self_karma: bool,
+
+    // How long a user has to wait before changing the same name's karma
+    // again. Zero disables the cooldown.
+    cooldown_secs: i64,
+
+    // How much karma, counting both increments and decrements, a user may
+    // give in a day. Zero means no limit.
+    daily_budget: i64,
+
+    // Whether karma is shared everywhere or kept separately per channel.
+    scope_mode: ScopeMode,
+
+    // Channel IDs which share karma with other channels in per-channel
+    // mode, mapped to the name of their group.
+    channel_groups: HashMap<String, String>,
+
+    // How far back "karma undo" reaches.
+    undo_secs: i64,
+
+    // Whether to skip karma changes inside inline code and URLs.
+    ignore_code_and_urls: bool,
+
+    // How old karma loses value when scores are shown.
+    decay: Decay,
+
+    // Where "karma export" writes its files.
+    export_dir: String,
+}
+
+impl Default for KarmaConfig {
+    fn default() -> Self {
+        KarmaConfig {
+            allow_self_karma: false,
+            cooldown_secs: 0,
+            daily_budget: 0,
+            scope_mode: ScopeMode::Global,
+            channel_groups: HashMap::new(),
+            undo_secs: 5 * 60,
+            ignore_code_and_urls: false,
+            decay: Decay::None,
+            export_dir: ".".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum ScopeMode {
+    #[default]
+    Global,
+    Channel,
+}
+
+impl std::str::FromStr for ScopeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "global" => Ok(ScopeMode::Global),
+            "channel" => Ok(ScopeMode::Channel),
+            _ => Err(format_err!("expected \"global\" or \"channel\"")),
+        }
+    }
+}
+
+/// Parse channel groups in the format "group1=chan1,chan2;group2=chan3".
+fn parse_channel_groups(groups: &str) -> Result<HashMap<String, String>> {
+    let mut ret = HashMap::new();
+
+    for group in groups.split_terminator(';') {
+        let (name, channels) = group
+            .split_once('=')
+            .ok_or_else(|| format_err!("invalid channel group \"{}\"", group))?;
+
+        for channel in channels.split_terminator(',') {
+            if ret
+                .insert(channel.trim().to_string(), name.trim().to_string())
+                .is_some()
+            {
+                return Err(format_err!("channel {} is in multiple groups", channel));
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+impl KarmaConfig {
+    fn from_env() -> Result<Self> {
+        let default = KarmaConfig::default();
+
+        Ok(KarmaConfig {
+            allow_self_karma: utils::env_var_or("KARMA_ALLOW_SELF", default.allow_self_karma)?,
+            cooldown_secs: utils::env_var_or("KARMA_COOLDOWN_SECS", default.cooldown_secs)?,
+            daily_budget: utils::env_var_or("KARMA_DAILY_BUDGET", default.daily_budget)?,
+            scope_mode: utils::env_var_or("KARMA_SCOPE", default.scope_mode)?,
+            channel_groups: parse_channel_groups(
+                &dotenvy::var("KARMA_CHANNEL_GROUPS").unwrap_or_default(),
+            )
+            .context("Invalid $KARMA_CHANNEL_GROUPS")?,
+            undo_secs: utils::env_var_or("KARMA_UNDO_SECS", default.undo_secs)?,
+            ignore_code_and_urls: utils::env_var_or(
+                "KARMA_IGNORE_CODE_AND_URLS",
+                default.ignore_code_and_urls,
+            )?,
+            decay: utils::env_var_or("KARMA_DECAY", default.decay)?,
+            export_dir: utils::env_var_or("KARMA_EXPORT_DIR", default.export_dir)?,
+        })
+    }
+}
+
+enum ParseState {
+    Nothing,
+
+    ChangeToAdd,
+    Add,
+
+    ChangeToSubtract,
+    Subtract,
+}
+
+/// Parse an input karma change string and return the resulting delta
+fn parse_karma_change(change_str: &str) -> Result<i32> {
+    let mut state = ParseState::Nothing;
+    let mut counter = 0;
+
+    let missing_successive_plus = "you need at least two successive \"+\"s to increment, silly!";
+    let missing_successive_minus = "you need at least two successive \"-\"s to decrement, silly!";
+
+    for c in change_str.chars() {
+        match (c, &state) {
+            ('+', ParseState::Nothing) => {
+                state = ParseState::ChangeToAdd;
+            }
+            ('+', ParseState::ChangeToAdd) => {
+                state = ParseState::Add;
+                counter += 1;
+            }
+            ('+', ParseState::Add) => {
+                counter += 1;
+            }
+            ('+', ParseState::ChangeToSubtract) => {
+                return Err(format_err!(missing_successive_plus));
+            }
+            ('+', ParseState::Subtract) => {
+                state = ParseState::ChangeToAdd;
+            }
+            ('-', ParseState::Nothing) => {
+                state = ParseState::ChangeToSubtract;
+            }
+            ('-', ParseState::ChangeToAdd) => {
+                return Err(format_err!(missing_successive_minus));
+            }
+            ('-', ParseState::Add) => {
+                state = ParseState::ChangeToSubtract;
+            }
+            ('-', ParseState::ChangeToSubtract) => {
+                state = ParseState::Subtract;
+                counter -= 1;
+            }
+            ('-', ParseState::Subtract) => {
+                counter -= 1;
+            }
+            (unsupported_char, _) => {
+                return Err(format_err!(
+                    "character \"{}\" not supported by the Karma Adjustment Bureau",
+                    unsupported_char
+                ));
+            }
+        }
+    }
+
+    match state {
+        ParseState::ChangeToAdd => Err(format_err!(missing_successive_plus)),
+        ParseState::ChangeToSubtract => Err(format_err!(missing_successive_minus)),
+        ParseState::Nothing => {
+            // This state should be impossible given the regex we use, but
+            // why not be safe?
+            Err(format_err!(
+                "you didn't even try to adjust karma on this one!"
+            ))
+        }
+        ParseState::Add | ParseState::Subtract => Ok(counter),
+    }
+}
+
+impl KarmaPlugin {
+    fn new(config: KarmaConfig) -> Self {
+        KarmaPlugin { config }
+    }
+
+    /// Get the scope karma changes in the given channel belong to. The empty
+    /// scope is used for global karma and for private messages.
+    fn scope(&self, channel_id: Option<&str>) -> String {
+        match (self.config.scope_mode, channel_id) {
+            (ScopeMode::Channel, Some(channel_id)) => {
+                match self.config.channel_groups.get(channel_id) {
+                    Some(group) => format!("group:{}", group),
+                    None => channel_id.to_string(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Get a name's karma in a scope, with decay applied if it's enabled.
+    async fn current(&self, conn: &sqlx::SqlitePool, scope: &str, name: &str) -> Result<Karma> {
+        if self.config.decay == Decay::None {
+            return Karma::get_by_name(conn, scope, name).await;
+        }
+
+        Karma::decayed_by_name(conn, scope, name, self.config.decay, now()).await
+    }
+
+    /// Check whether a giver is allowed to make a change, returning the
+    /// reason if they aren't. The nick is only used to stop people giving
+    /// themselves karma, limits go by user ID so changing nicks doesn't
+    /// reset them.
+    async fn check_change(
+        &self,
+        conn: &sqlx::SqlitePool,
+        giver: &str,
+        giver_id: &str,
+        name: &str,
+        change: i32,
+    ) -> Result<Option<String>> {
+        if change > 0 && !self.config.allow_self_karma {
+            let giver_name = Karma::sanitize_name(conn, giver).await?;
+            if giver_name == name || giver.to_lowercase() == name {
+                return Ok(Some(format!("{}, you can't give yourself karma", giver)));
+            }
+        }
+
+        let now = now();
+
+        if self.config.cooldown_secs > 0 {
+            if let Some(last) = KarmaEvent::last_given(conn, giver_id, name).await? {
+                let wait = last + self.config.cooldown_secs - now;
+                if wait > 0 {
+                    return Ok(Some(format!(
+                        "{}, you can change {}'s karma again in {}s",
+                        giver, name, wait
+                    )));
+                }
+            }
+        }
+
+        if self.config.daily_budget > 0 {
+            let used = KarmaEvent::total_given_since(conn, giver_id, now - 24 * 60 * 60).await?;
+            if used + i64::from(change.abs()) > self.config.daily_budget {
+                return Ok(Some(format!(
+                    "{}, you've used {} of your daily karma budget of {}",
+                    giver, used, self.config.daily_budget
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl KarmaPlugin {
+    async fn handle_command(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        let (subcommand, rest) = match arg.map(str::trim) {
+            Some(arg) => match arg.split_once(char::is_whitespace) {
+                Some((subcommand, rest)) => (Some(subcommand), Some(rest.trim())),
+                None => (Some(arg), None),
+            },
+            None => (None, None),
+        };
+
+        match subcommand {
+            Some("top") => self.handle_leaderboard(ctx, rest, false).await,
+            Some("bottom") => self.handle_leaderboard(ctx, rest, true).await,
+            Some("rank") => match rest.or_else(|| ctx.sender()) {
+                Some(name) => self.handle_rank(ctx, name).await,
+                None => Err(format_err!("usage: karma rank <name>")),
+            },
+            Some("why") => match rest {
+                Some(name) => self.handle_why(ctx, name).await,
+                None => Err(format_err!("usage: karma why <name>")),
+            },
+            Some("given") => match rest.or_else(|| ctx.sender()) {
+                Some(nick) => self.handle_given(ctx, nick).await,
+                None => Err(format_err!("usage: karma given <nick>")),
+            },
+            Some("global") => match rest.or_else(|| ctx.sender()) {
+                Some(name) => self.handle_global(ctx, name).await,
+                None => Err(format_err!("usage: karma global <name>")),
+            },
+            Some("undo") => match ctx.sender() {
+                Some(giver) => self.handle_undo(ctx, giver).await,
+                None => Err(format_err!("no sender found to undo karma for")),
+            },
+            Some("aliases") => match rest {
+                Some(target) => self.handle_aliases(ctx, target).await,
+                None => Err(format_err!("usage: karma aliases <target>")),
+            },
+            Some("seasons") => self.handle_seasons(ctx).await,
+            Some("season") => match rest.map(|rest| {
+                let (first, second) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (
+                    first,
+                    Some(second.trim()).filter(|second| !second.is_empty()),
+                )
+            }) {
+                Some(("end", _)) if !ctx.is_admin() => {
+                    ctx.mention_reply("only admins can use karma season end")
+                        .await
+                }
+                Some(("end", Some(season))) => self.handle_end_season(ctx, season).await,
+                Some((season, name)) if season != "end" => {
+                    self.handle_season(ctx, season, name).await
+                }
+                _ => Err(format_err!(
+                    "usage: karma season <season> [name] | karma season end <season>"
+                )),
+            },
+            Some(subcommand @ ("alias" | "unalias" | "merge" | "export")) if !ctx.is_admin() => {
+                ctx.mention_reply(&format!("only admins can use karma {}", subcommand))
+                    .await
+            }
+            Some("alias") => match rest.and_then(parse_alias_args) {
+                Some((name, target)) => self.handle_alias(ctx, name, target).await,
+                None => Err(format_err!("usage: karma alias <name> -> <target>")),
+            },
+            Some("unalias") => match rest {
+                Some(name) => self.handle_unalias(ctx, name).await,
+                None => Err(format_err!("usage: karma unalias <name>")),
+            },
+            Some("export") => match rest.unwrap_or("json").parse() {
+                Ok(format) => self.handle_export(ctx, format).await,
+                Err(_) => Err(format_err!("usage: karma export [json|csv]")),
+            },
+            Some("merge") => match rest.and_then(parse_alias_args) {
+                Some((from, into)) => self.handle_merge(ctx, from, into).await,
+                None => Err(format_err!("usage: karma merge <name> <target>")),
+            },
+            _ => match arg.or_else(|| ctx.sender()) {
+                Some(nick) => self.handle_karma(ctx, nick).await,
+                None => Err(format_err!(
+                    "no nick found to use for karma check (not provided in source message)"
+                )),
+            },
+        }
+    }
+
+    async fn handle_leaderboard(
+        &self,
+        ctx: &Arc<Context>,
+        arg: Option<&str>,
+        ascending: bool,
+    ) -> Result<()> {
+        let args = parse_leaderboard_args(arg)?;
+
+        // Windows never reach back past the start of the current season.
+        let since = match args.window {
+            Some(window) => {
+                Some((now() - window.seconds()).max(Karma::season_start(&ctx.get_db()).await?))
+            }
+            None => None,
+        };
+
+        let scope = self.scope(ctx.target_channel_id());
+        let entries = if since.is_none() && self.config.decay != Decay::None {
+            let mut entries =
+                Karma::decayed(&ctx.get_db(), &scope, self.config.decay, now()).await?;
+            if ascending {
+                entries.sort_by(|a, b| a.score.cmp(&b.score).then_with(|| a.name.cmp(&b.name)));
+            }
+            entries.truncate(args.limit as usize);
+            entries
+        } else {
+            Karma::leaderboard(&ctx.get_db(), &scope, args.limit, ascending, since).await?
+        };
+
+        let window = args
+            .window
+            .map(|window| format!(" {}", window.description()))
+            .unwrap_or_default();
+
+        if entries.is_empty() {
+            return ctx
+                .mention_reply(&format!("No karma has been given{}.", window))
+                .await;
+        }
+
+        let label = if ascending { "Bottom" } else { "Top" };
+        let entries = entries
+            .iter()
+            .enumerate()
+            .map(|(i, karma)| format!("{}. {} ({})", i + 1, karma.name, karma.score))
+            .join(", ");
+
+        ctx.mention_reply(&format!("{} karma{}: {}", label, window, entries))
+            .await
+    }
+
+    async fn handle_rank(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
+        let db = ctx.get_db();
+        let name = Karma::sanitize_name(&db, arg).await?;
+
+        let scope = self.scope(ctx.target_channel_id());
+
+        let rank = if self.config.decay == Decay::None {
+            match Karma::rank(&db, &scope, &name).await? {
+                Some((rank, total)) => {
+                    let karma = Karma::get_by_name(&db, &scope, &name).await?;
+                    Some((rank, total, karma.score))
+                }
+                None => None,
+            }
+        } else {
+            let entries = Karma::decayed(&db, &scope, self.config.decay, now()).await?;
+            entries
+                .iter()
+                .find(|karma| karma.name == name)
+                .map(|karma| {
+                    let above = entries.iter().filter(|other| other.score > karma.score);
+                    (above.count() as i64 + 1, entries.len() as i64, karma.score)
+                })
+        };
+
+        match rank {
+            Some((rank, total, score)) => {
+                ctx.mention_reply(&format!(
+                    "{} is ranked #{} of {} with karma {}",
+                    arg, rank, total, score
+                ))
+                .await
+            }
+            None => {
+                ctx.mention_reply(&format!("{} doesn't have any karma yet", arg))
+                    .await
+            }
+        }
+    }
+
+    async fn handle_why(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
+        let db = ctx.get_db();
+        let name = Karma::sanitize_name(&db, arg).await?;
+
+        let scope = self.scope(ctx.target_channel_id());
+        let events = KarmaEvent::recent_reasons(&db, &scope, &name, HISTORY_SIZE).await?;
+        if events.is_empty() {
+            return ctx
+                .mention_reply(&format!("Nobody has said why {} has karma", arg))
+                .await;
+        }
+
+        let reasons = events
+            .iter()
+            .map(|event| {
+                format!(
+                    "{} from {} ({})",
+                    format_delta(event.delta),
+                    event.giver.as_deref().unwrap_or("someone"),
+                    event.reason.as_deref().unwrap_or_default()
+                )
+            })
+            .join(", ");
+
+        ctx.mention_reply(&format!("Recent karma for {}: {}", arg, reasons))
+            .await
+    }
+
+    async fn handle_given(&self, ctx: &Arc<Context>, nick: &str) -> Result<()> {
+        let (events, total) = KarmaEvent::recent_given(&ctx.get_db(), nick, HISTORY_SIZE).await?;
+        if events.is_empty() {
+            return ctx
+                .mention_reply(&format!("{} hasn't given any karma", nick))
+                .await;
+        }
+
+        let given = events
+            .iter()
+            .map(|event| match &event.reason {
+                Some(reason) => {
+                    format!("{} {} ({})", event.name, format_delta(event.delta), reason)
+                }
+                None => format!("{} {}", event.name, format_delta(event.delta)),
+            })
+            .join(", ");
+
+        ctx.mention_reply(&format!(
+            "{} has made {} karma changes. Most recent: {}",
+            nick, total, given
+        ))
+        .await
+    }
+
+    async fn handle_undo(&self, ctx: &Arc<Context>, giver: &str) -> Result<()> {
+        let since = (now() - self.config.undo_secs).max(Karma::season_start(&ctx.get_db()).await?);
+        let undone = KarmaEvent::undo_since(&ctx.get_db(), giver, since).await?;
+
+        if undone.is_empty() {
+            return ctx
+                .mention_reply(&format!(
+                    "you haven't changed any karma in the last {}s",
+                    self.config.undo_secs
+                ))
+                .await;
+        }
+
+        let undone = undone
+            .iter()
+            .map(|(name, delta)| format!("{} {}", name, format_delta(*delta)))
+            .join(", ");
+
+        ctx.mention_reply(&format!("Undid {}", undone)).await
+    }
+
+    async fn handle_aliases(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
+        let db = ctx.get_db();
+        let target = Karma::sanitize_name(&db, arg).await?;
+
+        let aliases = Karma::aliases(&db, &target).await?;
+        if aliases.is_empty() {
+            return ctx
+                .mention_reply(&format!("{} doesn't have any aliases", target))
+                .await;
+        }
 
-        Ok(
-            sqlx::query!("SELECT target FROM karma_alias WHERE name=$1;", name)
-                .map(|row| row.target)
-                .fetch_optional(conn)
-                .await?
-                .unwrap_or_else(|| name.to_string()),
-        )
+        ctx.mention_reply(&format!("Aliases for {}: {}", target, aliases.join(", ")))
+            .await
     }
 
-    async fn get_by_name(conn: &sqlx::SqlitePool, name: &str) -> Result<Self> {
-        Ok(
-            sqlx::query_as!(Karma, "SELECT name, score FROM karma WHERE name=$1;", name)
-                .fetch_optional(conn)
-                .await?
-                .unwrap_or_else(|| Karma {
-                    name: name.to_string(),
-                    score: 0,
-                }),
-        )
+    async fn handle_alias(&self, ctx: &Arc<Context>, name: &str, target: &str) -> Result<()> {
+        let db = ctx.get_db();
+
+        let target = match Karma::add_alias(&db, name, target).await {
+            Ok(target) => target,
+            Err(err) => return ctx.mention_reply(&err.to_string()).await,
+        };
+
+        let mut reply = format!("{} is now an alias of {}", name, target);
+
+        let existing = Karma::get_global(&db, &name.to_lowercase()).await?;
+        if existing.score != 0 {
+            write!(
+                reply,
+                ". {} still has its own karma of {}, use karma merge to fold it in",
+                name, existing.score
+            )?;
+        }
+
+        ctx.mention_reply(&reply).await
     }
 
-    async fn create_or_update(conn: &sqlx::SqlitePool, name: &str, score: i32) -> Result<Self> {
-        sqlx::query!(
-            "INSERT INTO karma (name, score) VALUES ($1, $2)
-ON CONFLICT (name) DO UPDATE SET score=EXCLUDED.score+karma.score;",
-            name,
-            score
-        )
-        .execute(conn)
-        .await?;
+    async fn handle_unalias(&self, ctx: &Arc<Context>, name: &str) -> Result<()> {
+        if Karma::remove_alias(&ctx.get_db(), name).await? {
+            ctx.mention_reply(&format!("{} is no longer an alias", name))
+                .await
+        } else {
+            ctx.mention_reply(&format!("{} isn't an alias", name)).await
+        }
+    }
 
-        Karma::get_by_name(conn, name).await
+    async fn handle_merge(&self, ctx: &Arc<Context>, from: &str, into: &str) -> Result<()> {
+        match Karma::merge(&ctx.get_db(), from, into).await {
+            Ok(karma) => {
+                ctx.mention_reply(&format!(
+                    "Merged {} into {}, whose karma is now {}",
+                    from, karma.name, karma.score
+                ))
+                .await
+            }
+            Err(err) => ctx.mention_reply(&err.to_string()).await,
+        }
     }
-}
 
-pub struct KarmaPlugin;
+    async fn handle_seasons(&self, ctx: &Arc<Context>) -> Result<()> {
+        let seasons = Karma::seasons(&ctx.get_db()).await?;
+        if seasons.is_empty() {
+            return ctx.mention_reply("No karma seasons have ended yet").await;
+        }
 
-enum ParseState {
-    Nothing,
+        ctx.mention_reply(&format!("Past karma seasons: {}", seasons.join(", ")))
+            .await
+    }
 
-    ChangeToAdd,
-    Add,
+    async fn handle_season(
+        &self,
+        ctx: &Arc<Context>,
+        season: &str,
+        name: Option<&str>,
+    ) -> Result<()> {
+        let db = ctx.get_db();
+        let scope = self.scope(ctx.target_channel_id());
 
-    ChangeToSubtract,
-    Subtract,
-}
+        if let Some(arg) = name {
+            let name = Karma::sanitize_name(&db, arg).await?;
+            return match Karma::season_score(&db, season, &scope, &name).await? {
+                Some(score) => {
+                    ctx.mention_reply(&format!(
+                        "{}'s karma at the end of {} was {}",
+                        arg, season, score
+                    ))
+                    .await
+                }
+                None => {
+                    ctx.mention_reply(&format!("{} didn't have any karma in {}", arg, season))
+                        .await
+                }
+            };
+        }
 
-/// Parse an input karma change string and return the resulting delta
-fn parse_karma_change(change_str: &str) -> Result<i32> {
-    let mut state = ParseState::Nothing;
-    let mut counter = 0;
+        let entries =
+            Karma::season_leaderboard(&db, season, &scope, DEFAULT_LEADERBOARD_SIZE).await?;
+        if entries.is_empty() {
+            return ctx
+                .mention_reply(&format!("No karma was recorded for season {}", season))
+                .await;
+        }
 
-    let missing_successive_plus = "you need at least two successive \"+\"s to increment, silly!";
-    let missing_successive_minus = "you need at least two successive \"-\"s to decrement, silly!";
+        let entries = entries
+            .iter()
+            .enumerate()
+            .map(|(i, karma)| format!("{}. {} ({})", i + 1, karma.name, karma.score))
+            .join(", ");
 
-    for c in change_str.chars() {
-        match (c, &state) {
-            ('+', ParseState::Nothing) => {
-                state = ParseState::ChangeToAdd;
-            }
-            ('+', ParseState::ChangeToAdd) => {
-                state = ParseState::Add;
-                counter += 1;
-            }
-            ('+', ParseState::Add) => {
-                counter += 1;
-            }
-            ('+', ParseState::ChangeToSubtract) => {
-                return Err(format_err!(missing_successive_plus));
-            }
-            ('+', ParseState::Subtract) => {
-                state = ParseState::ChangeToAdd;
-            }
-            ('-', ParseState::Nothing) => {
-                state = ParseState::ChangeToSubtract;
-            }
-            ('-', ParseState::ChangeToAdd) => {
-                return Err(format_err!(missing_successive_minus));
-            }
-            ('-', ParseState::Add) => {
-                state = ParseState::ChangeToSubtract;
-            }
-            ('-', ParseState::ChangeToSubtract) => {
-                state = ParseState::Subtract;
-                counter -= 1;
-            }
-            ('-', ParseState::Subtract) => {
-                counter -= 1;
-            }
-            (unsupported_char, _) => {
-                return Err(format_err!(
-                    "character \"{}\" not supported by the Karma Adjustment Bureau",
-                    unsupported_char
-                ));
+        ctx.mention_reply(&format!("Top karma in {}: {}", season, entries))
+            .await
+    }
+
+    async fn handle_end_season(&self, ctx: &Arc<Context>, season: &str) -> Result<()> {
+        match Karma::end_season(&ctx.get_db(), season).await {
+            Ok(archived) => {
+                ctx.mention_reply(&format!(
+                    "Ended {}, archiving {} scores. Everyone starts over from 0.",
+                    season, archived
+                ))
+                .await
             }
+            Err(err) => ctx.mention_reply(&err.to_string()).await,
         }
     }
 
-    match state {
-        ParseState::ChangeToAdd => Err(format_err!(missing_successive_plus)),
-        ParseState::ChangeToSubtract => Err(format_err!(missing_successive_minus)),
-        ParseState::Nothing => {
-            // This state should be impossible given the regex we use, but
-            // why not be safe?
-            Err(format_err!(
-                "you didn't even try to adjust karma on this one!"
-            ))
-        }
-        ParseState::Add | ParseState::Subtract => Ok(counter),
+    async fn handle_export(&self, ctx: &Arc<Context>, format: transfer::Format) -> Result<()> {
+        let data = transfer::KarmaExport::load(&ctx.get_db()).await?;
+
+        let file_name = format!("karma-{}.{}", now(), format.extension());
+        let path = std::path::Path::new(&self.config.export_dir).join(&file_name);
+
+        let (scores, aliases, history) =
+            (data.scores.len(), data.aliases.len(), data.history.len());
+
+        // Writing the file is blocking, so keep it off the async runtime.
+        tokio::task::spawn_blocking(move || data.write(format, std::fs::File::create(path)?))
+            .await??;
+
+        // Only the file name is shown, since the directory is on the bot's
+        // host.
+        ctx.mention_reply(&format!(
+            "Exported {} scores, {} aliases and {} history entries to {}",
+            scores, aliases, history, file_name
+        ))
+        .await
     }
-}
 
-impl KarmaPlugin {
-    pub fn new() -> Self {
-        KarmaPlugin
+    async fn handle_global(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
+        let name = Karma::sanitize_name(&ctx.get_db(), arg).await?;
+        let karma = Karma::get_global(&ctx.get_db(), &name).await?;
+
+        ctx.mention_reply(&format!(
+            "{}'s karma across all channels is {}",
+            arg, karma.score
+        ))
+        .await
     }
-}
 
-impl KarmaPlugin {
     async fn handle_karma(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
+        let scope = self.scope(ctx.target_channel_id());
         let name = Karma::sanitize_name(&ctx.get_db(), arg).await?;
-        let karma = Karma::get_by_name(&ctx.get_db(), &name).await?;
+        let karma = self.current(&ctx.get_db(), &scope, &name).await?;
 
         ctx.mention_reply(&format!("{}'s karma is {}", arg, karma.score))
             .await?;
@@ -151,7 +1515,13 @@ impl KarmaPlugin {
     }
 
     async fn handle_privmsg(&self, ctx: &Arc<Context>, msg: &str) -> Result<()> {
-        let captures: Vec<_> = KARMA_RE.captures_iter(msg).collect();
+        let (msg, reason) = split_reason(msg);
+        let msg = if self.config.ignore_code_and_urls {
+            strip_ignored(msg)
+        } else {
+            msg.into()
+        };
+        let captures: Vec<_> = KARMA_RE.captures_iter(&msg).collect();
 
         if captures.is_empty() {
             return Ok(());
@@ -179,13 +1549,37 @@ impl KarmaPlugin {
         }
 
         let db = ctx.get_db();
+        let scope = self.scope(ctx.target_channel_id());
+        let source = ChangeSource {
+            scope: &scope,
+            giver: ctx.sender(),
+            giver_id: ctx.sender_id(),
+            channel_id: ctx.target_channel_id(),
+            reason,
+        };
 
         let mut lines = Vec::new();
 
         for (name, raw_change) in changes.into_iter() {
             let change = utils::clamp(raw_change, -5, 5);
 
-            let karma = Karma::create_or_update(&db, &name, change).await?;
+            if let Some(giver) = source.giver {
+                let giver_id = source
+                    .giver_id
+                    .ok_or_else(|| format_err!("Could not determine sender ID"))?;
+                if let Some(refusal) = self
+                    .check_change(&db, giver, giver_id, &name, change)
+                    .await?
+                {
+                    lines.push(refusal);
+                    continue;
+                }
+            }
+
+            let mut karma = Karma::create_or_update(&db, &name, change, &source).await?;
+            if self.config.decay != Decay::None {
+                karma = self.current(&db, &scope, &name).await?;
+            }
 
             let mut line = String::new();
 
@@ -212,15 +1606,25 @@ impl KarmaPlugin {
 #[async_trait]
 impl Plugin for KarmaPlugin {
     fn new_from_env() -> Result<Self> {
-        Ok(KarmaPlugin::new())
+        Ok(KarmaPlugin::new(KarmaConfig::from_env()?))
     }
 
     fn command_metadata(&self) -> Vec<CommandMetadata> {
         vec![CommandMetadata {
             name: "karma".to_string(),
-            short_help: "usage: karma [nick]. gives current karma of given nick.".to_string(),
+            short_help: "usage: karma [nick] | karma top|bottom [n] [day|week|month] | karma rank|given|global [nick] | karma why|aliases <name> | karma undo | karma alias|merge <name> -> <target> | karma unalias <name> | karma seasons | karma season <season> [nick] | karma season end <season> | karma export [json|csv]. gives current karma of given nick.".to_string(),
             full_help:
-                "gives current karma of given nick. if no nick provided, assumes message sender."
+                "gives current karma of given nick. if no nick provided, assumes message sender. \
+top and bottom list the highest and lowest scores, optionally counting only changes from the past day, week or month. \
+rank shows where a nick places among everyone with karma. \
+if karma is kept per channel, these all use the current channel and global sums a name's karma across every channel. \
+why shows recent reasons given with karma changes, like \"rust++ # for fixing the build\", and given shows the changes a nick has made recently. \
+undo reverts the karma changes you made in the last few minutes. \
+aliases lists the names which count towards a name. admins can add and remove aliases with alias and unalias, \
+and merge folds one name's karma and history into another before aliasing it. \
+seasons lists past karma seasons and season shows the top scores or a nick's score from one of them. \
+admins can end the current season with season end, which archives every score and starts over from 0. \
+admins can also export every score, alias and history entry to a JSON or CSV file on the bot's host with export."
                     .to_string(),
         }]
     }
@@ -230,14 +1634,7 @@ impl Plugin for KarmaPlugin {
 
         while let Ok(ctx) = stream.recv().await {
             let res = match ctx.as_event() {
-                Ok(Event::Command("karma", possible_arg)) => {
-                    match possible_arg.or_else(|| ctx.sender()) {
-                        Some(nick) => self.handle_karma(&ctx, nick).await,
-                        None => Err(format_err!(
-                            "no nick found to use for karma check (not provided in source message)"
-                        )),
-                    }
-                }
+                Ok(Event::Command("karma", arg)) => self.handle_command(&ctx, arg).await,
                 Ok(Event::Message(_, msg)) => self.handle_privmsg(&ctx, msg).await,
                 _ => Ok(()),
             };
@@ -252,6 +1649,7 @@ impl Plugin for KarmaPlugin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing;
 
     #[test]
     fn test_single_karma_increment() -> Result<()> {
@@ -312,4 +1710,507 @@ mod tests {
         assert!(parse_karma_change("++-++").is_err());
         assert!(parse_karma_change("--+--").is_err());
     }
+
+    #[test]
+    fn test_parse_leaderboard_args() -> Result<()> {
+        assert_eq!(
+            parse_leaderboard_args(None)?,
+            LeaderboardArgs {
+                limit: DEFAULT_LEADERBOARD_SIZE,
+                window: None
+            }
+        );
+        assert_eq!(
+            parse_leaderboard_args(Some("3"))?,
+            LeaderboardArgs {
+                limit: 3,
+                window: None
+            }
+        );
+        assert_eq!(
+            parse_leaderboard_args(Some("this week"))?,
+            LeaderboardArgs {
+                limit: DEFAULT_LEADERBOARD_SIZE,
+                window: Some(Window::Week)
+            }
+        );
+        assert_eq!(
+            parse_leaderboard_args(Some("Month 10"))?,
+            LeaderboardArgs {
+                limit: 10,
+                window: Some(Window::Month)
+            }
+        );
+
+        assert!(parse_leaderboard_args(Some("0")).is_err());
+        assert!(parse_leaderboard_args(Some("11")).is_err());
+        assert!(parse_leaderboard_args(Some("fortnight")).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leaderboard_and_rank() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        Karma::create_or_update(&db, "rust", 5, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "go", 2, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "java", -3, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "zig", 2, &ChangeSource::default()).await?;
+
+        let names = |entries: Vec<Karma>| entries.into_iter().map(|k| k.name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(Karma::leaderboard(&db, "", 3, false, None).await?),
+            vec!["rust", "go", "zig"]
+        );
+        assert_eq!(
+            names(Karma::leaderboard(&db, "", 2, true, None).await?),
+            vec!["java", "go"]
+        );
+
+        assert_eq!(Karma::rank(&db, "", "rust").await?, Some((1, 4)));
+        assert_eq!(Karma::rank(&db, "", "zig").await?, Some((2, 4)));
+        assert_eq!(Karma::rank(&db, "", "java").await?, Some((4, 4)));
+        assert_eq!(Karma::rank(&db, "", "cobol").await?, None);
+
+        // Pretend everything so far happened a long time ago.
+        sqlx::query!("UPDATE karma_events SET created_at=0;")
+            .execute(&db)
+            .await?;
+        Karma::create_or_update(&db, "java", 4, &ChangeSource::default()).await?;
+
+        let recent =
+            Karma::leaderboard(&db, "", 5, false, Some(now() - Window::Week.seconds())).await?;
+        assert_eq!(recent.len(), 1);
+        assert_eq!((recent[0].name.as_str(), recent[0].score), ("java", 4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_reason() {
+        assert_eq!(
+            split_reason("rust++ # for fixing the build"),
+            ("rust++", Some("for fixing the build"))
+        );
+        assert_eq!(
+            split_reason("rust++ go++ #  thanks  "),
+            ("rust++ go++", Some("thanks"))
+        );
+        assert_eq!(split_reason("rust++"), ("rust++", None));
+        assert_eq!(split_reason("rust++ in #rust"), ("rust++ in #rust", None));
+        assert_eq!(split_reason("rust++ #"), ("rust++ #", None));
+    }
+
+    #[tokio::test]
+    async fn test_history() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let source = ChangeSource {
+            giver: Some("Alice"),
+            channel_id: Some("#chan"),
+            reason: Some("for fixing the build"),
+            ..Default::default()
+        };
+        Karma::create_or_update(&db, "rust", 1, &source).await?;
+        Karma::create_or_update(
+            &db,
+            "rust",
+            -1,
+            &ChangeSource {
+                giver: Some("bob"),
+                ..Default::default()
+            },
+        )
+        .await?;
+        Karma::create_or_update(
+            &db,
+            "go",
+            2,
+            &ChangeSource {
+                giver: Some("alice"),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let reasons = KarmaEvent::recent_reasons(&db, "", "rust", 5).await?;
+        assert_eq!(reasons.len(), 1);
+        assert_eq!(reasons[0].giver.as_deref(), Some("Alice"));
+        assert_eq!(reasons[0].reason.as_deref(), Some("for fixing the build"));
+
+        let (given, total) = KarmaEvent::recent_given(&db, "ALICE", 1).await?;
+        assert_eq!(total, 2);
+        assert_eq!(given.len(), 1);
+        assert_eq!((given[0].name.as_str(), given[0].delta), ("go", 2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_change() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        sqlx::query!("INSERT INTO karma_alias (name, target) VALUES ('bobby', 'bob');")
+            .execute(&db)
+            .await?;
+
+        let plugin = KarmaPlugin::new(KarmaConfig::default());
+        assert!(plugin
+            .check_change(&db, "Bob", "U2", "bob", 1)
+            .await?
+            .is_some());
+        assert!(plugin
+            .check_change(&db, "bobby", "U3", "bob", 1)
+            .await?
+            .is_some());
+        assert!(plugin
+            .check_change(&db, "bob", "U2", "bob", -1)
+            .await?
+            .is_none());
+        assert!(plugin
+            .check_change(&db, "alice", "U1", "bob", 1)
+            .await?
+            .is_none());
+
+        let plugin = KarmaPlugin::new(KarmaConfig {
+            allow_self_karma: true,
+            cooldown_secs: 60,
+            daily_budget: 3,
+            ..Default::default()
+        });
+        assert!(plugin
+            .check_change(&db, "bob", "U2", "bob", 1)
+            .await?
+            .is_none());
+
+        let source = ChangeSource {
+            giver: Some("alice"),
+            giver_id: Some("U1"),
+            ..Default::default()
+        };
+        Karma::create_or_update(&db, "bob", 2, &source).await?;
+
+        // Alice just changed bob's karma, so she needs to wait.
+        let refusal = plugin.check_change(&db, "alice", "U1", "bob", 1).await?;
+        assert!(refusal.unwrap().contains("again in"));
+
+        // She has 1 point of her budget left.
+        assert!(plugin
+            .check_change(&db, "alice", "U1", "rust", -1)
+            .await?
+            .is_none());
+        let refusal = plugin.check_change(&db, "alice", "U1", "rust", 2).await?;
+        assert!(refusal.unwrap().contains("daily karma budget"));
+
+        // Limits follow the user ID, so changing nicks doesn't reset them
+        // and taking someone's nick doesn't use theirs up.
+        let refusal = plugin.check_change(&db, "alice2", "U1", "bob", 1).await?;
+        assert!(refusal.unwrap().contains("again in"));
+        let refusal = plugin.check_change(&db, "alice2", "U1", "rust", 2).await?;
+        assert!(refusal.unwrap().contains("daily karma budget"));
+        assert!(plugin
+            .check_change(&db, "alice", "U4", "bob", 2)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_alias_args() {
+        assert_eq!(
+            parse_alias_args("rustlang -> rust"),
+            Some(("rustlang", "rust"))
+        );
+        assert_eq!(
+            parse_alias_args("\"rust lang\" -> rust"),
+            Some(("rust lang", "rust"))
+        );
+        assert_eq!(
+            parse_alias_args("rustlang rust"),
+            Some(("rustlang", "rust"))
+        );
+        assert_eq!(parse_alias_args("rustlang"), None);
+        assert_eq!(parse_alias_args("a b c"), None);
+        assert_eq!(parse_alias_args("-> rust"), None);
+    }
+
+    #[tokio::test]
+    async fn test_aliases() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        assert_eq!(Karma::add_alias(&db, "rustlang", "Rust").await?, "rust");
+        assert_eq!(Karma::sanitize_name(&db, "RustLang").await?, "rust");
+
+        // Aliasing to an alias points at the final target.
+        assert_eq!(Karma::add_alias(&db, "ferris", "rustlang").await?, "rust");
+
+        // Aliasing a target moves its aliases along with it.
+        assert_eq!(Karma::add_alias(&db, "rust", "crab").await?, "crab");
+        assert_eq!(
+            Karma::aliases(&db, "crab").await?,
+            vec!["ferris", "rust", "rustlang"]
+        );
+
+        assert!(Karma::add_alias(&db, "crab", "ferris").await.is_err());
+        assert!(Karma::add_alias(&db, "crab", "crab").await.is_err());
+
+        assert!(Karma::remove_alias(&db, "Ferris").await?);
+        assert!(!Karma::remove_alias(&db, "ferris").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let source = ChangeSource::default();
+        Karma::create_or_update(&db, "golang", 3, &source).await?;
+        Karma::create_or_update(&db, "go", 2, &source).await?;
+        Karma::add_alias(&db, "gopher", "golang").await?;
+
+        let karma = Karma::merge(&db, "golang", "go").await?;
+        assert_eq!((karma.name.as_str(), karma.score), ("go", 5));
+
+        assert_eq!(Karma::get_global(&db, "golang").await?.score, 0);
+        assert_eq!(Karma::sanitize_name(&db, "golang").await?, "go");
+        assert_eq!(Karma::sanitize_name(&db, "gopher").await?, "go");
+
+        let history = Karma::leaderboard(&db, "", 5, false, Some(0)).await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].name.as_str(), history[0].score), ("go", 5));
+
+        assert!(Karma::merge(&db, "gopher", "go").await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_channel_groups() -> Result<()> {
+        let groups = parse_channel_groups("work=#dev, #ops;fun=#random")?;
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups["#dev"], "work");
+        assert_eq!(groups["#ops"], "work");
+        assert_eq!(groups["#random"], "fun");
+
+        assert!(parse_channel_groups("")?.is_empty());
+        assert!(parse_channel_groups("#dev").is_err());
+        assert!(parse_channel_groups("a=#dev;b=#dev").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scopes() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let plugin = KarmaPlugin::new(KarmaConfig {
+            scope_mode: ScopeMode::Channel,
+            channel_groups: parse_channel_groups("work=#dev,#ops")?,
+            ..Default::default()
+        });
+        assert_eq!(plugin.scope(Some("#dev")), "group:work");
+        assert_eq!(plugin.scope(Some("#random")), "#random");
+        assert_eq!(plugin.scope(None), "");
+        assert_eq!(
+            KarmaPlugin::new(KarmaConfig::default()).scope(Some("#dev")),
+            ""
+        );
+
+        let dev = plugin.scope(Some("#dev"));
+        let ops = plugin.scope(Some("#ops"));
+        let random = plugin.scope(Some("#random"));
+
+        for (scope, change) in [(&dev, 2), (&ops, 1), (&random, -5)] {
+            let source = ChangeSource {
+                scope,
+                ..Default::default()
+            };
+            Karma::create_or_update(&db, "lunch", change, &source).await?;
+        }
+
+        assert_eq!(Karma::get_by_name(&db, &ops, "lunch").await?.score, 3);
+        assert_eq!(Karma::get_by_name(&db, &random, "lunch").await?.score, -5);
+        assert_eq!(Karma::get_by_name(&db, "", "lunch").await?.score, 0);
+        assert_eq!(Karma::get_global(&db, "lunch").await?.score, -2);
+
+        assert_eq!(Karma::rank(&db, &random, "lunch").await?, Some((1, 1)));
+        assert_eq!(
+            Karma::leaderboard(&db, &dev, 5, false, Some(0)).await?[0].score,
+            3
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_ignored() {
+        assert!(!KARMA_RE.is_match(&strip_ignored("try `foo--` instead")));
+        assert!(!KARMA_RE.is_match(&strip_ignored("```\nwhile (x) bar++\n```")));
+        assert!(!KARMA_RE.is_match(&strip_ignored("see https://example.com/c++ now")));
+        assert!(KARMA_RE.is_match(&strip_ignored("rust++ `not--`")));
+    }
+
+    #[tokio::test]
+    async fn test_undo() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let alice = ChangeSource {
+            giver: Some("alice"),
+            ..Default::default()
+        };
+        let bob = ChangeSource {
+            giver: Some("bob"),
+            ..Default::default()
+        };
+
+        Karma::create_or_update(&db, "rust", 3, &bob).await?;
+        Karma::create_or_update(&db, "rust", -1, &alice).await?;
+        Karma::create_or_update(&db, "go", 2, &alice).await?;
+
+        // Pretend alice's change to rust was a while ago.
+        sqlx::query!("UPDATE karma_events SET created_at=0 WHERE giver='alice' AND name='rust';")
+            .execute(&db)
+            .await?;
+
+        let undone = KarmaEvent::undo_since(&db, "Alice", now() - 60).await?;
+        assert_eq!(undone, vec![("go".to_string(), 2)]);
+
+        assert_eq!(Karma::get_by_name(&db, "", "go").await?.score, 0);
+        assert_eq!(Karma::get_by_name(&db, "", "rust").await?.score, 2);
+        assert!(KarmaEvent::undo_since(&db, "alice", now() - 60)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_decay() -> Result<()> {
+        assert_eq!("none".parse::<Decay>()?, Decay::None);
+        assert_eq!("halve:30".parse::<Decay>()?, Decay::Halve { days: 30 });
+        assert_eq!(
+            "exponential:7.5".parse::<Decay>()?,
+            Decay::Exponential {
+                half_life_days: 7.5
+            }
+        );
+
+        assert!("halve".parse::<Decay>().is_err());
+        assert!("halve:0".parse::<Decay>().is_err());
+        assert!("exponential:-1".parse::<Decay>().is_err());
+        assert!("linear:10".parse::<Decay>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decay() -> Result<()> {
+        const DAY: i64 = 24 * 60 * 60;
+
+        let db = testing::memory_db().await?;
+        let now = now();
+
+        // Scores from before history was kept have no events at all.
+        sqlx::query!("INSERT INTO karma (scope, name, score) VALUES ('', 'perl', 8);")
+            .execute(&db)
+            .await?;
+
+        Karma::create_or_update(&db, "rust", 4, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "go", 4, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "python", 2, &ChangeSource::default()).await?;
+        let ten_days_ago = now - 10 * DAY;
+        sqlx::query!(
+            "UPDATE karma_events SET created_at=$1 WHERE name IN ('go', 'python');",
+            ten_days_ago
+        )
+        .execute(&db)
+        .await?;
+
+        // Part of python's score is from before its history was kept.
+        sqlx::query!("UPDATE karma SET score=6 WHERE name='python';")
+            .execute(&db)
+            .await?;
+
+        let scores = |entries: Vec<Karma>| -> Vec<(String, i64)> {
+            entries
+                .into_iter()
+                .map(|karma| (karma.name, karma.score))
+                .collect()
+        };
+
+        let cases = [
+            (
+                Decay::None,
+                vec![("perl", 8), ("python", 6), ("go", 4), ("rust", 4)],
+            ),
+            (
+                Decay::Halve { days: 7 },
+                vec![("perl", 8), ("rust", 4), ("python", 3), ("go", 2)],
+            ),
+            (
+                Decay::Exponential {
+                    half_life_days: 5.0,
+                },
+                vec![("perl", 8), ("rust", 4), ("python", 2), ("go", 1)],
+            ),
+        ];
+
+        for (decay, expected) in cases {
+            let expected: Vec<(String, i64)> = expected
+                .into_iter()
+                .map(|(name, score)| (name.to_string(), score))
+                .collect();
+            assert_eq!(
+                scores(Karma::decayed(&db, "", decay, now).await?),
+                expected,
+                "Failed with {:?}",
+                decay
+            );
+
+            for (name, score) in &expected {
+                let karma = Karma::decayed_by_name(&db, "", name, decay, now).await?;
+                assert_eq!(karma.score, *score, "Failed {} with {:?}", name, decay);
+            }
+
+            let missing = Karma::decayed_by_name(&db, "", "cobol", decay, now).await?;
+            assert_eq!(missing.score, 0);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seasons() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        assert!(Karma::end_season(&db, "empty").await.is_err());
+
+        Karma::create_or_update(&db, "rust", 3, &ChangeSource::default()).await?;
+        Karma::create_or_update(&db, "go", 1, &ChangeSource::default()).await?;
+
+        assert_eq!(Karma::end_season(&db, "2026").await?, 2);
+        assert!(Karma::season_start(&db).await? > 0);
+        assert_eq!(Karma::get_by_name(&db, "", "rust").await?.score, 0);
+
+        Karma::create_or_update(&db, "rust", 1, &ChangeSource::default()).await?;
+        assert!(Karma::end_season(&db, "2026").await.is_err());
+
+        assert_eq!(Karma::seasons(&db).await?, vec!["2026".to_string()]);
+        assert_eq!(Karma::season_score(&db, "2026", "", "rust").await?, Some(3));
+        assert_eq!(Karma::season_score(&db, "2026", "", "perl").await?, None);
+        assert_eq!(
+            Karma::season_leaderboard(&db, "2026", "", 5)
+                .await?
+                .into_iter()
+                .map(|karma| karma.name)
+                .collect::<Vec<_>>(),
+            vec!["rust".to_string(), "go".to_string()]
+        );
+
+        Ok(())
+    }
 }
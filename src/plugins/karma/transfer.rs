@@ -0,0 +1,601 @@
+//! Moving karma between bots. Scores, aliases and history can be exported to
+//! and imported from JSON or CSV, either from the command line or, for
+//! exports, from chat.
+
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::add_alias_tx;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// Guess the format from a file's extension, defaulting to JSON.
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".csv") {
+            Format::Csv
+        } else {
+            Format::Json
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format_err!("expected \"json\" or \"csv\"")),
+        }
+    }
+}
+
+/// What to do when an imported score or alias already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Strategy {
+    // Add the imported score to the existing one.
+    #[default]
+    Sum,
+
+    // Overwrite the existing score or alias.
+    Replace,
+
+    // Keep the existing score or alias.
+    Skip,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sum" => Ok(Strategy::Sum),
+            "replace" => Ok(Strategy::Replace),
+            "skip" => Ok(Strategy::Skip),
+            _ => Err(format_err!("expected \"sum\", \"replace\" or \"skip\"")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreRecord {
+    #[serde(default)]
+    pub scope: String,
+    pub name: String,
+    pub score: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AliasRecord {
+    pub name: String,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord {
+    #[serde(default)]
+    pub scope: String,
+    pub name: String,
+    pub delta: i64,
+    pub created_at: i64,
+    pub giver: Option<String>,
+    pub channel_id: Option<String>,
+    pub reason: Option<String>,
+}
+
+/// Everything needed to move karma from one bot to another.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KarmaExport {
+    #[serde(default)]
+    pub scores: Vec<ScoreRecord>,
+    #[serde(default)]
+    pub aliases: Vec<AliasRecord>,
+    #[serde(default)]
+    pub history: Vec<EventRecord>,
+}
+
+/// CSV can't nest, so every record is flattened into one row type and told
+/// apart by its kind. Value is the score for scores and the delta for
+/// history.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CsvRow {
+    kind: String,
+    scope: Option<String>,
+    name: String,
+    value: Option<i64>,
+    target: Option<String>,
+    created_at: Option<i64>,
+    giver: Option<String>,
+    channel_id: Option<String>,
+    reason: Option<String>,
+}
+
+impl KarmaExport {
+    pub async fn load(conn: &sqlx::SqlitePool) -> Result<Self> {
+        let scores = sqlx::query_as!(
+            ScoreRecord,
+            "SELECT scope, name, score FROM karma ORDER BY scope, name;"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        let aliases = sqlx::query_as!(
+            AliasRecord,
+            "SELECT name, target FROM karma_alias ORDER BY name;"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        let history = sqlx::query_as!(
+            EventRecord,
+            "SELECT scope, name, delta, created_at, giver, channel_id, reason FROM karma_events ORDER BY id;"
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(KarmaExport {
+            scores,
+            aliases,
+            history,
+        })
+    }
+
+    pub fn write<W: Write>(&self, format: Format, writer: W) -> Result<()> {
+        match format {
+            Format::Json => serde_json::to_writer_pretty(writer, self)?,
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+
+                for score in &self.scores {
+                    writer.serialize(CsvRow {
+                        kind: "score".to_string(),
+                        scope: Some(score.scope.clone()),
+                        name: score.name.clone(),
+                        value: Some(score.score),
+                        ..Default::default()
+                    })?;
+                }
+
+                for alias in &self.aliases {
+                    writer.serialize(CsvRow {
+                        kind: "alias".to_string(),
+                        name: alias.name.clone(),
+                        target: Some(alias.target.clone()),
+                        ..Default::default()
+                    })?;
+                }
+
+                for event in &self.history {
+                    writer.serialize(CsvRow {
+                        kind: "event".to_string(),
+                        scope: Some(event.scope.clone()),
+                        name: event.name.clone(),
+                        value: Some(event.delta),
+                        created_at: Some(event.created_at),
+                        giver: event.giver.clone(),
+                        channel_id: event.channel_id.clone(),
+                        reason: event.reason.clone(),
+                        ..Default::default()
+                    })?;
+                }
+
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(format: Format, reader: R) -> Result<Self> {
+        match format {
+            Format::Json => Ok(serde_json::from_reader(reader)?),
+            Format::Csv => {
+                let mut ret = KarmaExport::default();
+
+                for (i, row) in csv::Reader::from_reader(reader)
+                    .deserialize::<CsvRow>()
+                    .enumerate()
+                {
+                    // The header is line 1.
+                    let line = i + 2;
+                    let row = row?;
+                    let missing =
+                        |field| format_err!("line {}: {} is missing {}", line, row.kind, field);
+
+                    match row.kind.as_str() {
+                        "score" => ret.scores.push(ScoreRecord {
+                            scope: row.scope.clone().unwrap_or_default(),
+                            name: row.name.clone(),
+                            score: row.value.ok_or_else(|| missing("value"))?,
+                        }),
+                        "alias" => ret.aliases.push(AliasRecord {
+                            name: row.name.clone(),
+                            target: row.target.clone().ok_or_else(|| missing("target"))?,
+                        }),
+                        "event" => ret.history.push(EventRecord {
+                            scope: row.scope.clone().unwrap_or_default(),
+                            name: row.name.clone(),
+                            delta: row.value.ok_or_else(|| missing("value"))?,
+                            created_at: row.created_at.ok_or_else(|| missing("created_at"))?,
+                            giver: row.giver.clone(),
+                            channel_id: row.channel_id.clone(),
+                            reason: row.reason.clone(),
+                        }),
+                        kind => {
+                            return Err(format_err!("line {}: unknown kind \"{}\"", line, kind))
+                        }
+                    }
+                }
+
+                Ok(ret)
+            }
+        }
+    }
+}
+
+/// What an import changed, or would have changed for a dry run.
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub scores_added: u64,
+    pub scores_updated: u64,
+    pub scores_skipped: u64,
+    pub aliases_added: u64,
+    pub aliases_replaced: u64,
+    pub aliases_skipped: u64,
+    pub events_added: u64,
+    pub events_skipped: u64,
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Dry run, nothing was changed.")?;
+        }
+
+        writeln!(
+            f,
+            "Scores: {} added, {} updated, {} skipped",
+            self.scores_added, self.scores_updated, self.scores_skipped
+        )?;
+        writeln!(
+            f,
+            "Aliases: {} added, {} replaced, {} skipped",
+            self.aliases_added, self.aliases_replaced, self.aliases_skipped
+        )?;
+        write!(
+            f,
+            "History: {} added, {} already present",
+            self.events_added, self.events_skipped
+        )?;
+
+        for warning in &self.warnings {
+            write!(f, "\nwarning: {}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn resolve_name(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, name: &str) -> Result<String> {
+    let name = name.to_lowercase();
+
+    Ok(
+        sqlx::query!("SELECT target FROM karma_alias WHERE name=$1;", name)
+            .map(|row| row.target)
+            .fetch_optional(&mut **tx)
+            .await?
+            .unwrap_or(name),
+    )
+}
+
+/// Import karma, resolving conflicting scores and aliases with the given
+/// strategy. History is always merged in, skipping changes which are
+/// already present, so importing the same file twice doesn't duplicate it.
+///
+/// Everything happens in one transaction, which is rolled back for a dry
+/// run.
+pub async fn import(
+    conn: &sqlx::SqlitePool,
+    data: &KarmaExport,
+    strategy: Strategy,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut tx = conn.begin().await?;
+
+    // Aliases go first so imported scores and history land on the names
+    // they're counted towards.
+    for alias in &data.aliases {
+        let name = alias.name.to_lowercase();
+        let existing = sqlx::query!("SELECT target FROM karma_alias WHERE name=$1;", name)
+            .map(|row| row.target)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        if existing.is_some() && strategy != Strategy::Replace {
+            report.aliases_skipped += 1;
+            continue;
+        }
+
+        match add_alias_tx(&mut tx, &name, &alias.target).await {
+            Ok(target) if existing.as_deref() == Some(target.as_str()) => {
+                report.aliases_skipped += 1
+            }
+            Ok(_) if existing.is_some() => report.aliases_replaced += 1,
+            Ok(_) => report.aliases_added += 1,
+            Err(err) => {
+                report.aliases_skipped += 1;
+                report.warnings.push(err.to_string());
+            }
+        }
+    }
+
+    for score in &data.scores {
+        let name = resolve_name(&mut tx, &score.name).await?;
+        let existing = sqlx::query!(
+            "SELECT score FROM karma WHERE scope=$1 AND name=$2;",
+            score.scope,
+            name
+        )
+        .map(|row| row.score)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let new_score = match (existing, strategy) {
+            (None, _) => score.score,
+            (Some(_), Strategy::Skip) => {
+                report.scores_skipped += 1;
+                continue;
+            }
+            (Some(existing), Strategy::Sum) => existing + score.score,
+            (Some(_), Strategy::Replace) => score.score,
+        };
+
+        sqlx::query!(
+            "INSERT INTO karma (scope, name, score) VALUES ($1, $2, $3)
+ON CONFLICT (scope, name) DO UPDATE SET score=EXCLUDED.score;",
+            score.scope,
+            name,
+            new_score
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if existing.is_some() {
+            report.scores_updated += 1;
+        } else {
+            report.scores_added += 1;
+        }
+    }
+
+    for event in &data.history {
+        let name = resolve_name(&mut tx, &event.name).await?;
+        let existing = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM karma_events
+WHERE scope=$1 AND name=$2 AND delta=$3 AND created_at=$4 AND giver IS $5;"#,
+            event.scope,
+            name,
+            event.delta,
+            event.created_at,
+            event.giver
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .count;
+
+        if existing > 0 {
+            report.events_skipped += 1;
+            continue;
+        }
+
+        sqlx::query!(
+            "INSERT INTO karma_events (scope, name, delta, created_at, giver, channel_id, reason) VALUES ($1, $2, $3, $4, $5, $6, $7);",
+            event.scope,
+            name,
+            event.delta,
+            event.created_at,
+            event.giver,
+            event.channel_id,
+            event.reason,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        report.events_added += 1;
+    }
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    Ok(report)
+}
+
+const USAGE: &str = "usage:
+    karma export [--format json|csv] [<file>]
+    karma import [--format json|csv] [--strategy sum|replace|skip] [--dry-run] <file>
+
+The format defaults to the file's extension, or JSON. Exports go to stdout
+without a file and imports read stdin when the file is -.";
+
+/// Run the "karma" command line subcommands.
+pub async fn run_cli(conn: &sqlx::SqlitePool, args: &[String]) -> Result<()> {
+    let mut format = None;
+    let mut strategy = Strategy::default();
+    let mut dry_run = false;
+    let mut path = None;
+
+    let (command, mut rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest.iter()),
+        None => return Err(format_err!("{}", USAGE)),
+    };
+
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = Some(
+                    rest.next()
+                        .ok_or_else(|| format_err!("--format needs a value"))?
+                        .parse()?,
+                )
+            }
+            "--strategy" => {
+                strategy = rest
+                    .next()
+                    .ok_or_else(|| format_err!("--strategy needs a value"))?
+                    .parse()?
+            }
+            "--dry-run" => dry_run = true,
+            _ if path.is_none() => path = Some(arg.as_str()),
+            _ => return Err(format_err!("unexpected argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    let format = format.unwrap_or_else(|| path.map(Format::from_path).unwrap_or(Format::Json));
+
+    match (command, path) {
+        ("export", None | Some("-")) => {
+            KarmaExport::load(conn)
+                .await?
+                .write(format, std::io::stdout().lock())?;
+        }
+        ("export", Some(path)) => {
+            let data = KarmaExport::load(conn).await?;
+            data.write(format, std::fs::File::create(path)?)?;
+            eprintln!(
+                "Exported {} scores, {} aliases and {} history entries to {}",
+                data.scores.len(),
+                data.aliases.len(),
+                data.history.len(),
+                path
+            );
+        }
+        ("import", Some(path)) => {
+            let data = if path == "-" {
+                KarmaExport::read(format, std::io::stdin().lock())?
+            } else {
+                KarmaExport::read(format, std::fs::File::open(path)?)
+                    .with_context(|| format!("failed to read {}", path))?
+            };
+
+            let report = import(conn, &data, strategy, dry_run).await?;
+            println!("{}", report);
+        }
+        _ => return Err(format_err!("{}", USAGE)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ChangeSource, Karma};
+    use super::*;
+    use crate::testing;
+
+    fn sample() -> KarmaExport {
+        KarmaExport {
+            scores: vec![
+                ScoreRecord {
+                    scope: String::new(),
+                    name: "rust".to_string(),
+                    score: 5,
+                },
+                ScoreRecord {
+                    scope: "chan".to_string(),
+                    name: "bob".to_string(),
+                    score: 2,
+                },
+            ],
+            aliases: vec![AliasRecord {
+                name: "bobby".to_string(),
+                target: "bob".to_string(),
+            }],
+            history: vec![EventRecord {
+                scope: String::new(),
+                name: "rust".to_string(),
+                delta: 1,
+                created_at: 1000,
+                giver: Some("alice".to_string()),
+                channel_id: None,
+                reason: Some("for fixing the build, finally".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        for format in [Format::Json, Format::Csv] {
+            let mut buf = Vec::new();
+            sample().write(format, &mut buf)?;
+            assert_eq!(KarmaExport::read(format, &buf[..])?, sample());
+        }
+
+        assert!(KarmaExport::read(Format::Csv, &b"kind,name\nscore,rust\n"[..]).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_strategies() -> Result<()> {
+        for (strategy, score) in [
+            (Strategy::Sum, 8),
+            (Strategy::Replace, 5),
+            (Strategy::Skip, 3),
+        ] {
+            let db = testing::memory_db().await?;
+            Karma::create_or_update(&db, "rust", 3, &ChangeSource::default()).await?;
+
+            let report = import(&db, &sample(), strategy, false).await?;
+            assert_eq!(report.scores_added, 1);
+            assert_eq!(Karma::get_by_name(&db, "", "rust").await?.score, score);
+            assert_eq!(Karma::get_by_name(&db, "chan", "bob").await?.score, 2);
+            assert_eq!(Karma::sanitize_name(&db, "bobby").await?, "bob");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_dry_run() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let report = import(&db, &sample(), Strategy::Sum, true).await?;
+        assert_eq!(report.scores_added, 2);
+        assert_eq!(report.aliases_added, 1);
+        assert_eq!(report.events_added, 1);
+        assert_eq!(KarmaExport::load(&db).await?, KarmaExport::default());
+
+        // Importing twice doesn't duplicate history.
+        import(&db, &sample(), Strategy::Sum, false).await?;
+        let report = import(&db, &sample(), Strategy::Sum, false).await?;
+        assert_eq!((report.events_added, report.events_skipped), (0, 1));
+        assert_eq!(report.aliases_skipped, 1);
+        assert_eq!(KarmaExport::load(&db).await?.history, sample().history);
+
+        Ok(())
+    }
+}
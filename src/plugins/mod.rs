@@ -11,7 +11,7 @@ mod forecast;
 pub use self::forecast::ForecastPlugin;
 
 mod karma;
-pub use self::karma::KarmaPlugin;
+pub use self::karma::{transfer as karma_transfer, KarmaPlugin};
 
 mod mention;
 pub use self::mention::MentionPlugin;
@@ -42,3 +42,9 @@ pub use self::help::HelpPlugin;
 
 mod remind;
 pub use self::remind::RemindPlugin;
+
+mod tell;
+pub use self::tell::TellPlugin;
+
+mod timezone;
+pub use self::timezone::TimezonePlugin;
@@ -1,25 +1,41 @@
 use std::io::BufRead;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::prelude::*;
+use crate::utils::cache::Cache;
+
+const BASE_METAR_URL: &str = "https://tgftp.nws.noaa.gov/data/observations/metar/stations";
+const BASE_TAF_URL: &str = "https://tgftp.nws.noaa.gov/data/forecasts/taf/stations";
+
+// Station files are only refreshed a few times an hour.
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+const CACHE_SIZE: usize = 64;
 
 pub struct NoaaPlugin {
-    base_metar_url: &'static str,
-    base_taf_url: &'static str,
+    base_metar_url: String,
+    base_taf_url: String,
+    http: utils::http::Client,
+    cache: Cache<String>,
 }
 
 impl NoaaPlugin {
     pub fn new() -> Self {
+        Self::with_base_urls(BASE_METAR_URL.to_string(), BASE_TAF_URL.to_string())
+    }
+
+    pub fn with_base_urls(base_metar_url: String, base_taf_url: String) -> Self {
         NoaaPlugin {
-            base_metar_url: "https://tgftp.nws.noaa.gov/data/observations/metar/stations",
-            base_taf_url: "https://tgftp.nws.noaa.gov/data/forecasts/taf/stations",
+            base_metar_url,
+            base_taf_url,
+            http: utils::http::client(),
+            cache: Cache::new("noaa", CACHE_SIZE, CACHE_TTL),
         }
     }
 }
 
 impl NoaaPlugin {
     async fn lookup_metar(&self, ctx: &Context, station: String) -> Result<()> {
-        let mut lines = lines_from_url(self.base_metar_url, &station).await?;
+        let mut lines = self.lines_from_url(&self.base_metar_url, &station).await?;
 
         // Only set the station if a request was successful.
         NoaaLocation::set_for_name(
@@ -42,7 +58,7 @@ impl NoaaPlugin {
     }
 
     async fn lookup_taf(&self, ctx: &Context, station: String) -> Result<()> {
-        let mut lines = lines_from_url(self.base_taf_url, &station).await?;
+        let mut lines = self.lines_from_url(&self.base_taf_url, &station).await?;
 
         // Only set the station if a request was successful.
         NoaaLocation::set_for_name(
@@ -54,7 +70,7 @@ impl NoaaPlugin {
         .await?;
 
         let _ = lines.next();
-        for line in lines.flatten() {
+        for line in lines.map_while(|line| line.ok()) {
             ctx.mention_reply(line.trim()).await?;
         }
 
@@ -64,6 +80,7 @@ impl NoaaPlugin {
 
 #[derive(sqlx::FromRow, Debug)]
 pub struct NoaaLocation {
+    #[allow(dead_code)]
     pub nick: String,
     pub station: String,
 }
@@ -93,18 +110,36 @@ ON CONFLICT (nick) DO UPDATE SET station=EXCLUDED.station;",
     }
 }
 
-async fn lines_from_url(
+impl NoaaPlugin {
+    async fn lines_from_url(
+        &self,
+        base_url: &str,
+        station: &str,
+    ) -> Result<std::io::Lines<std::io::Cursor<String>>> {
+        let mut station = station.to_string();
+        station.make_ascii_uppercase();
+
+        let data = self
+            .cache
+            .get_or_fetch(&format!("{}/{}", base_url, station), || {
+                fetch_station(&self.http, base_url, &station)
+            })
+            .await?;
+
+        Ok(std::io::Cursor::new(data).lines())
+    }
+}
+
+async fn fetch_station(
+    http: &utils::http::Client,
     base_url: &str,
     station: &str,
-) -> Result<std::io::Lines<std::io::Cursor<String>>> {
-    let mut station = station.to_string();
-    station.make_ascii_uppercase();
-
+) -> Result<String> {
     for prefix in &["", "K", "W"] {
         let url = format!("{}/{}{}.TXT", base_url, prefix, station);
 
         let start = Instant::now();
-        let resp = reqwest::get(&url).await?;
+        let resp = http.get(&url, &()).await?;
         if resp.status() != reqwest::StatusCode::OK {
             info!(
                 "Station information at \"{}\" returned status {}",
@@ -121,7 +156,7 @@ async fn lines_from_url(
             start.elapsed().as_millis()
         );
 
-        return Ok(std::io::Cursor::new(data).lines());
+        return Ok(data);
     }
 
     Err(format_err!("Failed to get station data"))
@@ -182,6 +217,10 @@ impl Plugin for NoaaPlugin {
     }
 
     async fn run(self, bot: Arc<Client>) -> Result<()> {
+        if utils::cache::persistence_enabled()? {
+            self.cache.persist(bot.get_db());
+        }
+
         let mut stream = bot.subscribe();
 
         while let Ok(ctx) = stream.recv().await {
@@ -197,3 +236,35 @@ impl Plugin for NoaaPlugin {
         Err(format_err!("noaa plugin lagged"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttp;
+
+    #[tokio::test]
+    async fn test_lines_from_url_prefixes() -> Result<()> {
+        let server = FakeHttp::spawn(vec![
+            (404, ""),
+            (200, "2024/01/01 00:00\nKORD 010000Z 27010KT 10SM CLR"),
+        ])
+        .await?;
+
+        let plugin = NoaaPlugin::with_base_urls(server.url("/metar"), server.url("/taf"));
+        let mut lines = plugin.lines_from_url(&plugin.base_metar_url, "ord").await?;
+        assert_eq!(
+            lines.nth(1).transpose()?.as_deref(),
+            Some("KORD 010000Z 27010KT 10SM CLR")
+        );
+
+        let requests = server.requests();
+        assert!(requests[0].starts_with("GET /metar/ORD.TXT "));
+        assert!(requests[1].starts_with("GET /metar/KORD.TXT "));
+
+        // Asking again shouldn't go back to the server.
+        plugin.lines_from_url(&plugin.base_metar_url, "ord").await?;
+        assert_eq!(server.requests().len(), 2);
+
+        Ok(())
+    }
+}
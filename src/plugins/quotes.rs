@@ -1,61 +1,330 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
 use crate::prelude::*;
+use crate::scheduler::{self, now, Job};
+
+const SEARCH_RESULTS: i64 = 3;
+const TOP_SIZE: i64 = 5;
 
-#[derive(Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
-struct CacheKey {
-    channel: String,
+// How long the list of connected backends is kept before asking core again.
+const BACKENDS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A recent message which can be grabbed.
+#[derive(Debug)]
+struct CachedMessage {
     nick: String,
+    user_id: Option<String>,
+    text: String,
+    said_at: i64,
 }
 
-#[derive(Default)]
 pub struct QuotesPlugin {
-    message_cache: HashMap<CacheKey, Quote>,
+    config: QuotesConfig,
+
+    // The most recent messages in each channel, oldest first.
+    message_cache: HashMap<String, VecDeque<CachedMessage>>,
+
+    // IDs of the backends connected to core, along with when they were
+    // fetched.
+    backends: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+#[derive(Debug, Clone)]
+struct QuotesConfig {
+    // How many messages are kept per channel.
+    buffer_size: usize,
+
+    // Whether quotes are only returned in the channel they were grabbed in.
+    per_channel: bool,
+
+    // Channels which get a quote of the day. Empty disables it.
+    quote_of_the_day_channels: Vec<String>,
+
+    // When the quote of the day is posted, as a cron expression.
+    quote_of_the_day_schedule: String,
+}
+
+impl Default for QuotesConfig {
+    fn default() -> Self {
+        QuotesConfig {
+            buffer_size: 50,
+            per_channel: false,
+            quote_of_the_day_channels: Vec::new(),
+            quote_of_the_day_schedule: "0 0 9 * * *".to_string(),
+        }
+    }
+}
+
+impl QuotesConfig {
+    fn from_env() -> Result<Self> {
+        let default = QuotesConfig::default();
+
+        let config = QuotesConfig {
+            buffer_size: utils::env_var_or("QUOTES_BUFFER_SIZE", default.buffer_size)?,
+            per_channel: utils::env_var_or("QUOTES_PER_CHANNEL", default.per_channel)?,
+            quote_of_the_day_channels: dotenvy::var("QUOTES_OF_THE_DAY_CHANNELS")
+                .unwrap_or_default()
+                .split_terminator(',')
+                .map(|channel| channel.trim().to_string())
+                .collect(),
+            quote_of_the_day_schedule: utils::env_var_or(
+                "QUOTES_OF_THE_DAY_SCHEDULE",
+                default.quote_of_the_day_schedule,
+            )?,
+        };
+
+        // Catch bad schedules at startup rather than when they're first used.
+        scheduler::next_run(&config.quote_of_the_day_schedule, now())
+            .context("Invalid $QUOTES_OF_THE_DAY_SCHEDULE")?;
+
+        Ok(config)
+    }
 }
 
 impl QuotesPlugin {
-    pub fn new() -> Self {
+    fn new(config: QuotesConfig) -> Self {
         QuotesPlugin {
-            ..Default::default()
+            config,
+            message_cache: HashMap::new(),
+            backends: Mutex::new(None),
+        }
+    }
+
+    /// The channel quotes are limited to, if any. Private messages don't
+    /// have a channel, so nothing is visible from them in per-channel mode.
+    fn scope<'a>(&self, ctx: &'a Context) -> Option<&'a str> {
+        if self.config.per_channel {
+            Some(ctx.target_channel_id().unwrap_or_default())
+        } else {
+            None
         }
     }
+
+    /// Find which backend a channel belongs to, if it can be worked out.
+    async fn backend_for(&self, ctx: &Context, channel_id: &str) -> Option<String> {
+        if let Some((fetched_at, backends)) = &*self.backends.lock().unwrap() {
+            if fetched_at.elapsed() < BACKENDS_TTL {
+                return find_backend(backends, channel_id).map(str::to_string);
+            }
+        }
+
+        let backends: Vec<String> = match ctx.list_backends().await {
+            Ok(res) => res.backends.into_iter().map(|backend| backend.id).collect(),
+            Err(err) => {
+                warn!("failed to list backends: {}", err);
+                return None;
+            }
+        };
+
+        let backend = find_backend(&backends, channel_id).map(str::to_string);
+        *self.backends.lock().unwrap() = Some((Instant::now(), backends));
+
+        backend
+    }
+
+    /// Look up a quote by ID, hiding it if it isn't visible here.
+    async fn get_visible(&self, ctx: &Context, id: i64) -> Result<Option<Quote>> {
+        Ok(Quote::get(&ctx.get_db(), id)
+            .await?
+            .filter(|quote| quote.visible_in(self.scope(ctx))))
+    }
+}
+
+/// Which recent messages to grab: the start-th through end-th most recent
+/// messages from any of the nicks, counting from 1.
+#[derive(Debug, PartialEq)]
+struct GrabArgs<'a> {
+    nicks: Vec<&'a str>,
+    start: usize,
+    end: usize,
+}
+
+/// Parse "<nick>[,<nick>...] [n | n-m]".
+fn parse_grab_args(arg: &str) -> Option<GrabArgs<'_>> {
+    let mut parts = arg.split_whitespace();
+
+    let nicks: Vec<&str> = parts
+        .next()?
+        .split(',')
+        .filter(|nick| !nick.is_empty())
+        .collect();
+    if nicks.is_empty() {
+        return None;
+    }
+
+    let (start, end) = match parts.next() {
+        None => (1, 1),
+        Some(range) => match range.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => {
+                let n = range.parse().ok()?;
+                (n, n)
+            }
+        },
+    };
+
+    if parts.next().is_some() || start == 0 || start > end {
+        return None;
+    }
+
+    Some(GrabArgs { nicks, start, end })
+}
+
+/// Pick the messages to grab out of a channel's buffer, returning where they
+/// are in it, oldest first. If there aren't enough, returns how many matching
+/// messages there were.
+fn select_messages(
+    buffer: &VecDeque<CachedMessage>,
+    args: &GrabArgs<'_>,
+) -> std::result::Result<Vec<usize>, usize> {
+    let matching: Vec<usize> = buffer
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, message)| {
+            args.nicks
+                .iter()
+                .any(|nick| nick.eq_ignore_ascii_case(&message.nick))
+        })
+        .map(|(pos, _)| pos)
+        .collect();
+
+    if matching.len() < args.end {
+        return Err(matching.len());
+    }
+
+    Ok(matching[args.start - 1..args.end]
+        .iter()
+        .rev()
+        .copied()
+        .collect())
+}
+
+/// Drop messages which have been grabbed from a channel's buffer, so
+/// grabbing again doesn't save the same quote twice. Positions must be in
+/// order, as returned by select_messages.
+fn forget_messages(buffer: &mut VecDeque<CachedMessage>, positions: &[usize]) {
+    for pos in positions.iter().rev() {
+        buffer.remove(*pos);
+    }
+}
+
+/// Join grabbed messages into a single quote. Lines are only labelled with
+/// who said them when there's more than one speaker.
+fn format_messages(messages: &[&CachedMessage]) -> String {
+    let single_speaker = messages.iter().map(|message| &message.nick).all_equal();
+
+    messages
+        .iter()
+        .map(|message| {
+            if single_speaker {
+                message.text.clone()
+            } else {
+                format!("<{}> {}", message.nick, message.text)
+            }
+        })
+        .join(" | ")
+}
+
+/// Find which of the backends a channel belongs to. Channel IDs from core
+/// are URLs like "irc://libera/%23seabird", where the host is the ID of the
+/// backend.
+fn find_backend<'a>(backends: &'a [String], channel_id: &str) -> Option<&'a str> {
+    let rest = channel_id
+        .split_once("://")
+        .map_or(channel_id, |(_, rest)| rest);
+    let (host, _) = rest.split_once('/')?;
+
+    backends
+        .iter()
+        .map(String::as_str)
+        .find(|backend| *backend == host)
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
 }
 
 impl QuotesPlugin {
     async fn handle_grab(&mut self, ctx: &Context, arg: Option<&str>) -> Result<()> {
-        let nick = match arg {
+        let args = match arg.and_then(parse_grab_args) {
             None => {
-                ctx.mention_reply(&format!("missing argument")).await?;
+                ctx.mention_reply("usage: grab <nick>[,<nick>...] [n | n-m]")
+                    .await?;
                 return Ok(());
             }
-            Some(nick) => nick,
+            Some(args) => args,
         };
 
-        let cache_key = CacheKey {
-            channel: ctx.target_channel_id().unwrap_or("unknown").to_string(),
-            nick: nick.to_string(),
-        };
+        let channel = ctx.target_channel_id().unwrap_or("unknown");
+        let selected = self
+            .message_cache
+            .get(channel)
+            .map_or(Err(0), |buffer| select_messages(buffer, &args));
 
-        let quote = match self.message_cache.remove(&cache_key) {
-            None => {
+        let positions = match selected {
+            Ok(positions) => positions,
+            Err(found) if found > 0 => {
                 ctx.mention_reply(&format!(
-                    "latest message from {} in channel {} not found",
-                    cache_key.nick, cache_key.channel
+                    "only found {} recent messages from {} in this channel",
+                    found,
+                    args.nicks.join(", ")
+                ))
+                .await?;
+                return Ok(());
+            }
+            Err(_) => {
+                ctx.mention_reply(&format!(
+                    "no recent messages from {} found in this channel",
+                    args.nicks.join(", ")
                 ))
                 .await?;
                 return Ok(());
             }
-            Some(quote) => quote,
+        };
+        let buffer = &self.message_cache[channel];
+        let messages: Vec<&CachedMessage> = positions.iter().map(|pos| &buffer[*pos]).collect();
+
+        let speakers: Vec<(&str, Option<&str>)> = messages
+            .iter()
+            .map(|message| (message.nick.as_str(), message.user_id.as_deref()))
+            .unique_by(|(nick, _)| *nick)
+            .collect();
+        let nick = speakers.iter().map(|(nick, _)| nick).join(", ");
+        let text = format_messages(&messages);
+
+        let channel_id = ctx.target_channel_id();
+        let backend = match channel_id {
+            Some(channel_id) => self.backend_for(ctx, channel_id).await,
+            None => None,
         };
 
         let conn = ctx.get_db();
-        sqlx::query!(
-            "INSERT INTO quotes (nick, quote) VALUES ($1, $2)",
-            quote.nick,
-            quote.quote,
+        let id = Quote::insert(
+            &conn,
+            &NewQuote {
+                nick: &nick,
+                speakers,
+                quote: &text,
+                grabber: ctx.sender(),
+                grabber_id: ctx.sender_id(),
+                channel_id,
+                backend: backend.as_deref(),
+                said_at: Some(messages[0].said_at),
+            },
         )
-        .execute(&conn)
         .await?;
 
-        ctx.mention_reply(&format!("saved {}'s message: {}", quote.nick, quote.quote))
+        if let Some(buffer) = self.message_cache.get_mut(channel) {
+            forget_messages(buffer, &positions);
+        }
+
+        ctx.mention_reply(&format!("saved quote #{} from {}: {}", id, nick, text))
             .await?;
 
         Ok(())
@@ -64,22 +333,40 @@ impl QuotesPlugin {
     async fn handle_quote(&self, ctx: &Context, arg: Option<&str>) -> Result<()> {
         let conn = ctx.get_db();
 
-        let nick = match arg {
+        let arg = match arg.map(str::trim) {
+            None | Some("") => None,
+            Some(arg) => Some(arg),
+        };
+
+        let arg = match arg {
             None => {
-                ctx.mention_reply(&format!("missing argument")).await?;
+                match Quote::random(&conn, None, self.scope(ctx)).await? {
+                    Some(quote) => ctx.mention_reply(&quote.to_string()).await?,
+                    None => ctx.mention_reply("no quotes found").await?,
+                }
                 return Ok(());
             }
-            Some(nick) => nick,
+            Some(arg) => arg,
         };
 
-        let quote = match sqlx::query_as!(
-            Quote,
-            "SELECT nick, quote FROM quotes WHERE nick=$1 ORDER BY random() LIMIT 1;",
-            nick
-        )
-        .fetch_optional(&conn)
-        .await?
-        {
+        if let Some(id) = arg.strip_prefix('#') {
+            return self.handle_quote_by_id(ctx, id).await;
+        }
+
+        if arg == "top" {
+            return self.handle_top(ctx).await;
+        }
+
+        let nick = match arg.split_once(char::is_whitespace) {
+            Some(("+1", id)) => return self.handle_vote(ctx, id.trim(), 1).await,
+            Some(("-1", id)) => return self.handle_vote(ctx, id.trim(), -1).await,
+            Some(("delete", id)) => return self.handle_delete(ctx, id.trim()).await,
+            Some(("info", id)) => return self.handle_info(ctx, id.trim()).await,
+            Some(("search", terms)) => return self.handle_search(ctx, terms.trim()).await,
+            _ => arg,
+        };
+
+        let quote = match Quote::random(&conn, Some(nick), self.scope(ctx)).await? {
             None => {
                 ctx.mention_reply(&format!("no quotes from {} found", nick))
                     .await?;
@@ -88,32 +375,214 @@ impl QuotesPlugin {
             Some(quote) => quote,
         };
 
-        ctx.mention_reply(&format!("quote from {}: {}", quote.nick, quote.quote))
+        ctx.mention_reply(&quote.to_string()).await?;
+
+        Ok(())
+    }
+
+    async fn handle_quote_by_id(&self, ctx: &Context, id: &str) -> Result<()> {
+        let id = match parse_quote_id(id) {
+            Some(id) => id,
+            None => {
+                ctx.mention_reply("usage: quote #<id>").await?;
+                return Ok(());
+            }
+        };
+
+        match self.get_visible(ctx, id).await? {
+            Some(quote) => ctx.mention_reply(&quote.to_string()).await?,
+            None => {
+                ctx.mention_reply(&format!("quote #{} not found", id))
+                    .await?
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_delete(&self, ctx: &Context, id: &str) -> Result<()> {
+        let id = match parse_quote_id(id) {
+            Some(id) => id,
+            None => {
+                ctx.mention_reply("usage: quote delete <id>").await?;
+                return Ok(());
+            }
+        };
+
+        let conn = ctx.get_db();
+
+        let quote = match self.get_visible(ctx, id).await? {
+            Some(quote) => quote,
+            None => {
+                ctx.mention_reply(&format!("quote #{} not found", id))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let allowed = ctx.is_admin()
+            || match ctx.sender_id() {
+                Some(user_id) => quote.can_be_deleted_by(&conn, user_id).await?,
+                None => false,
+            };
+
+        if !allowed {
+            ctx.mention_reply(&format!(
+                "only {}, whoever grabbed it or an admin can delete quote #{}",
+                quote.nick, id
+            ))
             .await?;
+            return Ok(());
+        }
+
+        Quote::delete(&conn, id).await?;
+
+        ctx.mention_reply(&format!("deleted quote #{}", id)).await?;
 
         Ok(())
     }
 
-    async fn handle_message(&mut self, ctx: &Context, _sender: &str, msg: &str) -> Result<()> {
-        let nick = ctx.sender().unwrap_or("unknown").to_string();
+    async fn handle_search(&self, ctx: &Context, terms: &str) -> Result<()> {
+        let quotes = Quote::search(&ctx.get_db(), terms, self.scope(ctx), SEARCH_RESULTS).await?;
+        if quotes.is_empty() {
+            ctx.mention_reply(&format!("no quotes matching {} found", terms))
+                .await?;
+            return Ok(());
+        }
+
+        let results = quotes
+            .iter()
+            .map(|quote| format!("#{} {}: {}", quote.id, quote.nick, quote.quote))
+            .join(" | ");
+
+        ctx.mention_reply(&format!("quotes matching {}: {}", terms, results))
+            .await?;
+
+        Ok(())
+    }
 
-        let cache_key = CacheKey {
-            channel: ctx.target_channel_id().unwrap_or("unknown").to_string(),
-            nick: nick.clone(),
+    async fn handle_vote(&self, ctx: &Context, id: &str, vote: i64) -> Result<()> {
+        let id = match parse_quote_id(id) {
+            Some(id) => id,
+            None => {
+                ctx.mention_reply("usage: quote +1|-1 <id>").await?;
+                return Ok(());
+            }
         };
 
-        let quote = Quote {
-            nick,
-            quote: msg.to_string(),
+        if self.get_visible(ctx, id).await?.is_none() {
+            ctx.mention_reply(&format!("quote #{} not found", id))
+                .await?;
+            return Ok(());
+        }
+
+        // Anyone can use a nick, so votes are tied to user IDs.
+        let voter = match ctx.sender_id() {
+            Some(voter) => voter,
+            None => {
+                ctx.mention_reply("couldn't tell who you are").await?;
+                return Ok(());
+            }
         };
 
-        log::debug!(
-            "Latest quotes cache key: {:?}, quote: {:?}",
-            cache_key,
-            quote
-        );
+        let conn = ctx.get_db();
+        if !Quote::vote(&conn, id, voter, vote).await? {
+            ctx.mention_reply(&format!("you already voted on quote #{}", id))
+                .await?;
+            return Ok(());
+        }
+
+        ctx.mention_reply(&format!(
+            "quote #{} now has a score of {}",
+            id,
+            Quote::score(&conn, id).await?
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn handle_top(&self, ctx: &Context) -> Result<()> {
+        let quotes = Quote::top(&ctx.get_db(), self.scope(ctx), TOP_SIZE).await?;
+        if quotes.is_empty() {
+            ctx.mention_reply("no quotes have been voted on yet")
+                .await?;
+            return Ok(());
+        }
+
+        let quotes = quotes
+            .iter()
+            .map(|(quote, score)| {
+                format!(
+                    "#{} ({:+}) {}: {}",
+                    quote.id, score, quote.nick, quote.quote
+                )
+            })
+            .join(" | ");
+
+        ctx.mention_reply(&format!("top quotes: {}", quotes))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Post a random quote to a channel as its quote of the day.
+    async fn deliver_quote_of_the_day(&self, bot: &Client, job: &Job) -> Result<()> {
+        let channel: String = job.payload()?;
+
+        // The channel may have been removed from the config since the job
+        // was scheduled.
+        if !self.config.quote_of_the_day_channels.contains(&channel) {
+            bot.scheduler().cancel("quotes", &job.key).await?;
+            return Ok(());
+        }
+
+        let scope = self.config.per_channel.then_some(channel.as_str());
+        if let Some(quote) = Quote::random(&bot.get_db(), None, scope).await? {
+            bot.send_message(&channel, &format!("Quote of the day: {}", quote))
+                .await?;
+        }
 
-        self.message_cache.insert(cache_key, quote);
+        bot.scheduler().complete(job).await
+    }
+
+    async fn handle_info(&self, ctx: &Context, id: &str) -> Result<()> {
+        let id = match parse_quote_id(id) {
+            Some(id) => id,
+            None => {
+                ctx.mention_reply("usage: quote info <id>").await?;
+                return Ok(());
+            }
+        };
+
+        match self.get_visible(ctx, id).await? {
+            Some(quote) => ctx.mention_reply(&quote.info()).await?,
+            None => {
+                ctx.mention_reply(&format!("quote #{} not found", id))
+                    .await?
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, ctx: &Context, _sender: &str, msg: &str) -> Result<()> {
+        let channel = ctx.target_channel_id().unwrap_or("unknown").to_string();
+
+        let message = CachedMessage {
+            nick: ctx.sender().unwrap_or("unknown").to_string(),
+            user_id: ctx.sender_id().map(str::to_string),
+            text: msg.to_string(),
+            said_at: now(),
+        };
+
+        log::debug!("Caching message in {}: {:?}", channel, message);
+
+        let buffer = self.message_cache.entry(channel).or_default();
+        buffer.push_back(message);
+        while buffer.len() > self.config.buffer_size {
+            buffer.pop_front();
+        }
 
         Ok(())
     }
@@ -121,47 +590,774 @@ impl QuotesPlugin {
 
 #[derive(sqlx::FromRow, Hash, Debug)]
 pub struct Quote {
+    pub id: i64,
     pub nick: String,
     pub quote: String,
+    pub grabber: Option<String>,
+    pub grabber_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub backend: Option<String>,
+    pub said_at: Option<i64>,
+    pub grabbed_at: Option<i64>,
+}
+
+/// A quote which is about to be saved.
+#[derive(Debug, Default)]
+struct NewQuote<'a> {
+    // Who the quote is shown as being from. For quotes with several
+    // speakers, this is their nicks joined with ", ".
+    nick: &'a str,
+
+    // The nick and, if known, user ID of everyone who said part of the
+    // quote. If empty, only nick did.
+    speakers: Vec<(&'a str, Option<&'a str>)>,
+
+    quote: &'a str,
+    grabber: Option<&'a str>,
+    grabber_id: Option<&'a str>,
+    channel_id: Option<&'a str>,
+    backend: Option<&'a str>,
+    said_at: Option<i64>,
+}
+
+impl std::fmt::Display for Quote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "quote #{} from {}: {}", self.id, self.nick, self.quote)
+    }
+}
+
+impl Quote {
+    /// Save a quote, returning its ID.
+    async fn insert(conn: &sqlx::SqlitePool, quote: &NewQuote<'_>) -> Result<i64> {
+        let grabbed_at = now();
+
+        let mut tx = conn.begin().await?;
+
+        let id = sqlx::query!(
+            "INSERT INTO quotes (nick, quote, grabber, grabber_id, channel_id, backend, said_at, grabbed_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            quote.nick,
+            quote.quote,
+            quote.grabber,
+            quote.grabber_id,
+            quote.channel_id,
+            quote.backend,
+            quote.said_at,
+            grabbed_at,
+        )
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+        let only_nick = [(quote.nick, None)];
+        let speakers = if quote.speakers.is_empty() {
+            &only_nick[..]
+        } else {
+            &quote.speakers[..]
+        };
+
+        for (nick, user_id) in speakers {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO quote_speakers (quote_id, nick, user_id) VALUES ($1, $2, $3);",
+                id,
+                nick,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    async fn get(conn: &sqlx::SqlitePool, id: i64) -> Result<Option<Self>> {
+        Ok(sqlx::query_as!(
+            Quote,
+            "SELECT id, nick, quote, grabber, grabber_id, channel_id, backend, said_at, grabbed_at FROM quotes WHERE id=$1;",
+            id
+        )
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Pick a random quote, optionally only from one nick or one channel.
+    /// Quotes which have been voted up are more likely to be picked.
+    async fn random(
+        conn: &sqlx::SqlitePool,
+        nick: Option<&str>,
+        channel_id: Option<&str>,
+    ) -> Result<Option<Self>> {
+        Quote::pick(conn, nick, channel_id, rand::random()).await
+    }
+
+    /// Pick the quote found at the given fraction of the way through the
+    /// candidates' combined weight. Each vote up adds another chance of
+    /// being picked, relative to a quote nobody has voted on, and each vote
+    /// down makes it proportionally less likely.
+    async fn pick(
+        conn: &sqlx::SqlitePool,
+        nick: Option<&str>,
+        channel_id: Option<&str>,
+        fraction: f64,
+    ) -> Result<Option<Self>> {
+        Ok(sqlx::query_as!(
+            Quote,
+            r#"WITH candidates AS (
+    SELECT quotes.id, COALESCE(scores.score, 0) AS score FROM quotes
+    LEFT JOIN (SELECT quote_id, SUM(vote) AS score FROM quote_votes GROUP BY quote_id) AS scores
+        ON scores.quote_id = quotes.id
+    WHERE ($1 IS NULL OR quotes.id IN (SELECT quote_id FROM quote_speakers WHERE nick=$1))
+    AND ($2 IS NULL OR quotes.channel_id=$2)
+),
+weighted AS (
+    SELECT id, CASE WHEN score >= 0 THEN 1.0 + score ELSE 1.0 / (1.0 - score) END AS weight
+    FROM candidates
+),
+cumulative AS (
+    SELECT id, SUM(weight) OVER (ORDER BY id) AS running, SUM(weight) OVER () AS total
+    FROM weighted
+)
+SELECT quotes.id as "id!", nick, quote, grabber, grabber_id, channel_id, backend, said_at, grabbed_at
+FROM cumulative JOIN quotes ON quotes.id = cumulative.id
+WHERE cumulative.running > $3 * cumulative.total
+ORDER BY cumulative.running LIMIT 1;"#,
+            nick,
+            channel_id,
+            fraction
+        )
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Record a user's vote on a quote, replacing any earlier vote. Returns
+    /// false if they had already cast the same vote.
+    async fn vote(conn: &sqlx::SqlitePool, id: i64, voter: &str, vote: i64) -> Result<bool> {
+        let result = sqlx::query!(
+            "INSERT INTO quote_votes (quote_id, voter, vote) VALUES ($1, $2, $3)
+ON CONFLICT (quote_id, voter) DO UPDATE SET vote=EXCLUDED.vote WHERE vote != EXCLUDED.vote;",
+            id,
+            voter,
+            vote
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn score(conn: &sqlx::SqlitePool, id: i64) -> Result<i64> {
+        Ok(sqlx::query!(
+            r#"SELECT COALESCE(SUM(vote), 0) as "score!: i64" FROM quote_votes WHERE quote_id=$1;"#,
+            id
+        )
+        .fetch_one(conn)
+        .await?
+        .score)
+    }
+
+    /// Get the highest scoring quotes along with their scores, optionally
+    /// only from one channel.
+    async fn top(
+        conn: &sqlx::SqlitePool,
+        channel_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<(Self, i64)>> {
+        Ok(sqlx::query!(
+            r#"SELECT quotes.id as "id!", nick, quote, grabber, grabber_id, channel_id, backend, said_at, grabbed_at,
+    SUM(vote) as "score!: i64"
+FROM quote_votes JOIN quotes ON quotes.id = quote_votes.quote_id
+WHERE $1 IS NULL OR channel_id=$1
+GROUP BY quotes.id HAVING SUM(vote) > 0
+ORDER BY SUM(vote) DESC, quotes.id ASC LIMIT $2;"#,
+            channel_id,
+            limit
+        )
+        .map(|row| {
+            (
+                Quote {
+                    id: row.id,
+                    nick: row.nick,
+                    quote: row.quote,
+                    grabber: row.grabber,
+                    grabber_id: row.grabber_id,
+                    channel_id: row.channel_id,
+                    backend: row.backend,
+                    said_at: row.said_at,
+                    grabbed_at: row.grabbed_at,
+                },
+                row.score,
+            )
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Find the quotes which best match all of the given words, optionally
+    /// only from one channel.
+    async fn search(
+        conn: &sqlx::SqlitePool,
+        terms: &str,
+        channel_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let query = fts_query(terms);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(sqlx::query_as!(
+            Quote,
+            r#"SELECT quotes.id as "id!", nick, quotes.quote, grabber, grabber_id, channel_id, backend, said_at, grabbed_at
+FROM quotes_fts JOIN quotes ON quotes.id = quotes_fts.rowid
+WHERE quotes_fts MATCH $1 AND ($2 IS NULL OR channel_id=$2)
+ORDER BY quotes_fts.rank LIMIT $3;"#,
+            query,
+            channel_id,
+            limit
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    async fn delete(conn: &sqlx::SqlitePool, id: i64) -> Result<bool> {
+        let result = sqlx::query!("DELETE FROM quotes WHERE id=$1;", id)
+            .execute(conn)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Whether this quote can be shown when quotes are limited to the given
+    /// channel. Quotes grabbed before channels were recorded are only
+    /// visible when quotes aren't limited.
+    fn visible_in(&self, channel_id: Option<&str>) -> bool {
+        channel_id.is_none() || self.channel_id.as_deref() == channel_id
+    }
+
+    /// Describe where and when this quote was said and grabbed.
+    fn info(&self) -> String {
+        let mut info = format!("quote #{} from {}", self.id, self.nick);
+
+        if let Some(channel_id) = &self.channel_id {
+            write!(info, " in {}", channel_id).unwrap();
+        }
+        if let Some(backend) = &self.backend {
+            write!(info, " on {}", backend).unwrap();
+        }
+        if let Some(said_at) = self.said_at {
+            write!(info, ", said at {}", format_timestamp(said_at)).unwrap();
+        }
+
+        match (&self.grabber, self.grabbed_at) {
+            (Some(grabber), Some(grabbed_at)) => write!(
+                info,
+                ", grabbed by {} at {}",
+                grabber,
+                format_timestamp(grabbed_at)
+            ),
+            (Some(grabber), None) => write!(info, ", grabbed by {}", grabber),
+            (None, Some(grabbed_at)) => {
+                write!(info, ", grabbed at {}", format_timestamp(grabbed_at))
+            }
+            (None, None) => write!(info, ", grabbed before details were recorded"),
+        }
+        .unwrap();
+
+        info
+    }
+
+    /// Whether a user may delete this quote without being an admin, which
+    /// is only the case if they were quoted or grabbed it. Users are matched
+    /// by ID, since anyone can use their nick.
+    async fn can_be_deleted_by(&self, conn: &sqlx::SqlitePool, user_id: &str) -> Result<bool> {
+        if self.grabber_id.as_deref() == Some(user_id) {
+            return Ok(true);
+        }
+
+        let speaker = sqlx::query!(
+            "SELECT nick FROM quote_speakers WHERE quote_id=$1 AND user_id=$2 LIMIT 1;",
+            self.id,
+            user_id
+        )
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(speaker.is_some())
+    }
+}
+
+/// Turn search terms into an FTS5 query which matches quotes containing
+/// every word. Each word is quoted so anything users type is matched
+/// literally rather than being treated as query syntax.
+fn fts_query(terms: &str) -> String {
+    terms
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .join(" ")
+}
+
+/// Parse a quote ID, with or without a leading #.
+fn parse_quote_id(id: &str) -> Option<i64> {
+    id.trim_start_matches('#').parse().ok()
 }
 
 #[async_trait]
 impl Plugin for QuotesPlugin {
     fn new_from_env() -> Result<Self> {
-        Ok(QuotesPlugin::new())
+        Ok(QuotesPlugin::new(QuotesConfig::from_env()?))
     }
 
     fn command_metadata(&self) -> Vec<CommandMetadata> {
         vec![
             CommandMetadata {
                 name: "grab".to_string(),
-                short_help: "usage: grab [nick]. Saves the latest message by nick in this channel as a quote.".to_string(),
-                ..Default::default()
+                short_help: "usage: grab <nick>[,<nick>...] [n | n-m]. Saves the latest message by nick in this channel as a quote.".to_string(),
+                full_help: "saves the latest message by nick in this channel as a quote. \
+grab nick 3 saves their third most recent message and grab nick 1-3 saves their last three messages as one quote. \
+listing several nicks, like grab alice,bob 1-4, counts messages from any of them so a conversation can be saved.".to_string(),
             },
             CommandMetadata {
                 name: "quote".to_string(),
-                short_help: "usage: quote [nick]. Returns a random quote by a user in a channel.".to_string(),
-                ..Default::default()
+                short_help: "usage: quote [nick] | quote #<id> | quote info|delete|+1|-1 <id> | quote search <terms> | quote top. Returns a random quote, optionally by a given user.".to_string(),
+                full_help: "returns a random quote from anyone or by a user, or the quote with the given ID. \
+search finds the quotes which best match all of the given words. \
+info shows where and when a quote was said and who grabbed it. \
++1 and -1 vote on a quote, and quotes with higher scores are picked more often. top lists the highest scoring quotes. \
+quotes can be deleted by the user who was quoted, whoever grabbed them, or an admin.".to_string(),
             },
         ]
     }
 
     async fn run(mut self, bot: Arc<Client>) -> Result<()> {
         let mut stream = bot.subscribe();
+        let mut jobs = bot.scheduler().subscribe("quotes");
+
+        for channel in &self.config.quote_of_the_day_channels {
+            bot.scheduler()
+                .schedule_recurring(
+                    "quotes",
+                    &format!("quote-of-the-day:{}", channel),
+                    &self.config.quote_of_the_day_schedule,
+                    channel,
+                )
+                .await?;
+        }
+
+        loop {
+            tokio::select! {
+                result = stream.recv() => {
+                    let ctx = result.map_err(|_| format_err!("quotes plugin lagged"))?;
+                    let res = match ctx.as_event() {
+                        Ok(Event::Command("grab", arg)) => self.handle_grab(&ctx, arg).await,
+                        Ok(Event::Command("quote", arg)) => self.handle_quote(&ctx, arg).await,
+                        Ok(Event::Message(sender, message)) => {
+                            self.handle_message(&ctx, sender, message).await
+                        }
+                        _ => Ok(()),
+                    };
 
-        while let Ok(ctx) = stream.recv().await {
-            let res = match ctx.as_event() {
-                Ok(Event::Command("grab", arg)) => self.handle_grab(&ctx, arg).await,
-                Ok(Event::Command("quote", arg)) => self.handle_quote(&ctx, arg).await,
-                Ok(Event::Message(sender, message)) => {
-                    self.handle_message(&ctx, sender, message).await
+                    crate::check_err(&ctx, res).await;
                 }
-                _ => Ok(()),
-            };
+                Some(job) = jobs.recv() => {
+                    if let Err(e) = self.deliver_quote_of_the_day(&bot, &job).await {
+                        error!("Failed to post quote of the day: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_parse_quote_id() {
+        assert_eq!(parse_quote_id("12"), Some(12));
+        assert_eq!(parse_quote_id("#12"), Some(12));
+        assert_eq!(parse_quote_id("twelve"), None);
+        assert_eq!(parse_quote_id(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_quote_ids() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let first = Quote::insert(
+            &db,
+            &NewQuote {
+                nick: "alice",
+                speakers: vec![("alice", Some("u-alice"))],
+                quote: "hello",
+                grabber: Some("bob"),
+                grabber_id: Some("u-bob"),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let second = Quote::insert(
+            &db,
+            &NewQuote {
+                nick: "carol",
+                quote: "world",
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_ne!(first, second);
+
+        let quote = Quote::get(&db, first).await?.expect("missing quote");
+        assert_eq!(
+            quote.to_string(),
+            format!("quote #{} from alice: hello", first)
+        );
+        assert!(quote.can_be_deleted_by(&db, "u-alice").await?);
+        assert!(quote.can_be_deleted_by(&db, "u-bob").await?);
+        assert!(!quote.can_be_deleted_by(&db, "u-carol").await?);
+
+        assert!(Quote::delete(&db, first).await?);
+        assert!(!Quote::delete(&db, first).await?);
+        assert!(Quote::get(&db, first).await?.is_none());
+
+        // IDs of deleted quotes aren't reused, even for the newest quote.
+        Quote::delete(&db, second).await?;
+        let third = Quote::insert(
+            &db,
+            &NewQuote {
+                nick: "alice",
+                quote: "again",
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert!(third > second);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quote_metadata() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let id = Quote::insert(
+            &db,
+            &NewQuote {
+                nick: "alice",
+                quote: "hello",
+                grabber: Some("bob"),
+                channel_id: Some("irc.libera/#seabird"),
+                backend: Some("irc.libera"),
+                said_at: Some(0),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let quote = Quote::get(&db, id).await?.expect("missing quote");
+        assert!(quote.grabbed_at.is_some());
+        assert!(quote
+            .info()
+            .starts_with("quote #1 from alice in irc.libera/#seabird on irc.libera, said at 1970-01-01 00:00 UTC, grabbed by bob at "));
+
+        assert!(quote.visible_in(None));
+        assert!(quote.visible_in(Some("irc.libera/#seabird")));
+        assert!(!quote.visible_in(Some("irc.libera/#private")));
+
+        assert!(Quote::random(&db, Some("alice"), None).await?.is_some());
+        assert!(
+            Quote::random(&db, Some("alice"), Some("irc.libera/#seabird"))
+                .await?
+                .is_some()
+        );
+        assert!(
+            Quote::random(&db, Some("alice"), Some("irc.libera/#private"))
+                .await?
+                .is_none()
+        );
 
-            crate::check_err(&ctx, res).await;
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_backend() {
+        let backends = vec!["irc".to_string(), "irc2".to_string()];
+
+        assert_eq!(find_backend(&backends, "irc://irc/%23seabird"), Some("irc"));
+        assert_eq!(
+            find_backend(&backends, "irc://irc2/%23seabird"),
+            Some("irc2")
+        );
+        assert_eq!(find_backend(&backends, "irc2/#seabird"), Some("irc2"));
+        assert_eq!(find_backend(&backends, "irc://irc3/%23seabird"), None);
+        assert_eq!(find_backend(&backends, "irc"), None);
+    }
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("hello world"), r#""hello" "world""#);
+        assert_eq!(fts_query(r#"say "hi" OR*"#), r#""say" """hi""" "OR*""#);
+        assert_eq!(fts_query("  "), "");
+    }
+
+    #[tokio::test]
+    async fn test_search() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        for (nick, quote) in [
+            ("alice", "the build is broken again"),
+            ("bob", "who broke the build?"),
+            ("carol", "the build is fine, the tests are broken"),
+        ] {
+            Quote::insert(
+                &db,
+                &NewQuote {
+                    nick,
+                    quote,
+                    ..Default::default()
+                },
+            )
+            .await?;
         }
 
-        Err(format_err!("quotes plugin lagged"))
+        let nicks = |quotes: Vec<Quote>| {
+            quotes
+                .into_iter()
+                .map(|quote| quote.nick)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            nicks(Quote::search(&db, "broken build", None, 5).await?),
+            vec!["alice", "carol"]
+        );
+        assert_eq!(
+            nicks(Quote::search(&db, "BROKE", None, 5).await?),
+            vec!["bob"]
+        );
+        assert!(Quote::search(&db, "\"unbalanced AND", None, 5)
+            .await?
+            .is_empty());
+
+        // Deleted quotes drop out of the index.
+        Quote::delete(&db, 1).await?;
+        assert_eq!(
+            nicks(Quote::search(&db, "broken build", None, 5).await?),
+            vec!["carol"]
+        );
+
+        assert!(Quote::random(&db, None, None).await?.is_some());
+
+        Ok(())
+    }
+
+    fn buffer(messages: &[(&str, &str)]) -> VecDeque<CachedMessage> {
+        messages
+            .iter()
+            .enumerate()
+            .map(|(i, (nick, text))| CachedMessage {
+                nick: nick.to_string(),
+                user_id: None,
+                text: text.to_string(),
+                said_at: i as i64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_grab_args() {
+        let args = |nicks: &[&'static str], start, end| {
+            Some(GrabArgs {
+                nicks: nicks.to_vec(),
+                start,
+                end,
+            })
+        };
+
+        assert_eq!(parse_grab_args("alice"), args(&["alice"], 1, 1));
+        assert_eq!(parse_grab_args("alice 3"), args(&["alice"], 3, 3));
+        assert_eq!(parse_grab_args("alice 1-3"), args(&["alice"], 1, 3));
+        assert_eq!(
+            parse_grab_args("alice,bob 2-4"),
+            args(&["alice", "bob"], 2, 4)
+        );
+
+        assert_eq!(parse_grab_args(""), None);
+        assert_eq!(parse_grab_args(","), None);
+        assert_eq!(parse_grab_args("alice 0"), None);
+        assert_eq!(parse_grab_args("alice 3-1"), None);
+        assert_eq!(parse_grab_args("alice x"), None);
+        assert_eq!(parse_grab_args("alice 1 2"), None);
+    }
+
+    #[test]
+    fn test_select_messages() {
+        let mut buffer = buffer(&[
+            ("alice", "one"),
+            ("bob", "two"),
+            ("alice", "three"),
+            ("carol", "four"),
+            ("alice", "five"),
+        ]);
+
+        let grab = |buffer: &VecDeque<CachedMessage>, arg| {
+            select_messages(buffer, &parse_grab_args(arg).unwrap()).map(|positions| {
+                format_messages(
+                    &positions
+                        .iter()
+                        .map(|pos| &buffer[*pos])
+                        .collect::<Vec<_>>(),
+                )
+            })
+        };
+
+        assert_eq!(grab(&buffer, "alice"), Ok("five".to_string()));
+        assert_eq!(grab(&buffer, "Alice 2"), Ok("three".to_string()));
+        assert_eq!(
+            grab(&buffer, "alice 1-3"),
+            Ok("one | three | five".to_string())
+        );
+        assert_eq!(
+            grab(&buffer, "alice,bob 2-4"),
+            Ok("<alice> one | <bob> two | <alice> three".to_string())
+        );
+        assert_eq!(grab(&buffer, "alice 4"), Err(3));
+        assert_eq!(grab(&buffer, "dave"), Err(0));
+
+        // Grabbed messages can't be grabbed again.
+        let positions = select_messages(&buffer, &parse_grab_args("alice 1-2").unwrap()).unwrap();
+        forget_messages(&mut buffer, &positions);
+        assert_eq!(grab(&buffer, "alice"), Ok("one".to_string()));
+        assert_eq!(grab(&buffer, "alice 2"), Err(1));
+        assert_eq!(
+            grab(&buffer, "bob,carol 1-2"),
+            Ok("<bob> two | <carol> four".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_speakers() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let id = Quote::insert(
+            &db,
+            &NewQuote {
+                nick: "alice, bob",
+                speakers: vec![("alice", Some("u-alice")), ("bob", None)],
+                quote: "<alice> hi | <bob> hey",
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let quote = Quote::random(&db, Some("bob"), None)
+            .await?
+            .expect("missing quote");
+        assert_eq!(quote.id, id);
+        assert!(quote.can_be_deleted_by(&db, "u-alice").await?);
+        assert!(!quote.can_be_deleted_by(&db, "u-carol").await?);
+
+        // Speakers whose user ID wasn't known can't be recognized, even if
+        // someone uses their nick.
+        assert!(!quote.can_be_deleted_by(&db, "bob").await?);
+
+        Quote::delete(&db, id).await?;
+        assert!(Quote::random(&db, Some("bob"), None).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pick() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let mut ids = Vec::new();
+        for nick in ["alice", "bob", "carol"] {
+            ids.push(
+                Quote::insert(
+                    &db,
+                    &NewQuote {
+                        nick,
+                        quote: "hello",
+                        ..Default::default()
+                    },
+                )
+                .await?,
+            );
+        }
+
+        // Scores of 3, 0 and -3 give weights of 4, 1 and 0.25.
+        for voter in ["u1", "u2", "u3"] {
+            Quote::vote(&db, ids[0], voter, 1).await?;
+            Quote::vote(&db, ids[2], voter, -1).await?;
+        }
+
+        let pick = |fraction| {
+            let db = db.clone();
+            async move {
+                Quote::pick(&db, None, None, fraction)
+                    .await
+                    .map(|quote| quote.map(|quote| quote.nick))
+            }
+        };
+
+        assert_eq!(pick(0.0).await?.as_deref(), Some("alice"));
+        assert_eq!(pick(0.75).await?.as_deref(), Some("alice"));
+        assert_eq!(pick(0.8).await?.as_deref(), Some("bob"));
+        assert_eq!(pick(0.96).await?.as_deref(), Some("carol"));
+
+        assert_eq!(
+            Quote::pick(&db, Some("bob"), None, 0.99)
+                .await?
+                .map(|quote| quote.id),
+            Some(ids[1])
+        );
+        assert!(Quote::pick(&db, Some("dave"), None, 0.5).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_votes() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let mut ids = Vec::new();
+        for nick in ["alice", "bob", "carol"] {
+            ids.push(
+                Quote::insert(
+                    &db,
+                    &NewQuote {
+                        nick,
+                        quote: "hello",
+                        ..Default::default()
+                    },
+                )
+                .await?,
+            );
+        }
+
+        assert!(Quote::vote(&db, ids[0], "u1", 1).await?);
+        assert!(!Quote::vote(&db, ids[0], "u1", 1).await?);
+        assert!(Quote::vote(&db, ids[0], "u2", 1).await?);
+        assert!(Quote::vote(&db, ids[1], "u1", 1).await?);
+        assert!(Quote::vote(&db, ids[2], "u1", -1).await?);
+        assert_eq!(Quote::score(&db, ids[0]).await?, 2);
+
+        // Changing a vote replaces it rather than adding another.
+        assert!(Quote::vote(&db, ids[0], "u2", -1).await?);
+        assert_eq!(Quote::score(&db, ids[0]).await?, 0);
+        assert!(Quote::vote(&db, ids[0], "u2", 1).await?);
+
+        let top: Vec<_> = Quote::top(&db, None, 5)
+            .await?
+            .into_iter()
+            .map(|(quote, score)| (quote.nick, score))
+            .collect();
+        assert_eq!(top, vec![("alice".to_string(), 2), ("bob".to_string(), 1)]);
+
+        Ok(())
     }
 }
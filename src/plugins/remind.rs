@@ -1,84 +1,301 @@
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
 use crate::prelude::*;
+use crate::scheduler::{self, Job};
+use crate::utils::when::{
+    format_duration, parse_recurrence_prefix, parse_when, parse_when_prefix, Recurrence, When,
+};
+
+// How long after a reminder is delivered it can still be snoozed.
+const SNOOZE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// How absolute times are shown, like "Fri Oct 16 17:30 CDT".
+const TIME_FORMAT: &str = "%a %b %-d %H:%M %Z";
+
+// How long reminders are snoozed for when no time is given.
+const DEFAULT_SNOOZE: &str = "10m";
+
+// How long delivered reminders are kept before they're cleaned up.
+const DELIVERED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// The longest we'll wait between attempts to send a reminder.
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+
+pub struct RemindPlugin {
+    config: RemindConfig,
+}
+
+#[derive(Debug, Clone)]
+struct RemindConfig {
+    // How many times we try to send a reminder before giving up on it.
+    max_attempts: i64,
+
+    // How long to wait before trying to send a reminder again. This doubles
+    // after each failed attempt.
+    retry_secs: i64,
+}
+
+impl Default for RemindConfig {
+    fn default() -> Self {
+        RemindConfig {
+            max_attempts: 5,
+            retry_secs: 30,
+        }
+    }
+}
+
+impl RemindConfig {
+    fn from_env() -> Result<Self> {
+        let default = RemindConfig::default();
+
+        let config = RemindConfig {
+            max_attempts: utils::env_var_or("REMIND_MAX_ATTEMPTS", default.max_attempts)?,
+            retry_secs: utils::env_var_or("REMIND_RETRY_SECS", default.retry_secs)?,
+        };
+
+        if config.max_attempts < 1 {
+            return Err(format_err!("$REMIND_MAX_ATTEMPTS must be at least 1"));
+        }
+        if config.retry_secs < 0 {
+            return Err(format_err!("$REMIND_RETRY_SECS can't be negative"));
+        }
+
+        Ok(config)
+    }
+
+    /// How long to wait after the given number of failed attempts.
+    fn retry_delay(&self, attempts: i64) -> i64 {
+        let doublings = (attempts - 1).clamp(0, 16) as u32;
+        self.retry_secs
+            .saturating_mul(1 << doublings)
+            .min(MAX_RETRY_DELAY_SECS)
+    }
+}
+
+/// The result of trying to claim a reminder for delivery.
+#[derive(Debug, PartialEq)]
+enum Claim {
+    /// The reminder is ours to send. This is the number of attempts so far,
+    /// including this one.
+    Claimed(i64),
+
+    /// The reminder was cancelled or has already been dealt with.
+    Gone,
+
+    /// An earlier attempt was interrupted part way through, so the reminder
+    /// may have been sent already. It's marked as failed rather than risking
+    /// sending it twice.
+    Interrupted,
+
+    /// Like Interrupted, but for a recurring reminder. It's left alone so
+    /// only this occurrence can be given up on and the series can carry on.
+    InterruptedOccurrence,
+}
+
+const INTERRUPTED_ERROR: &str = "delivery was interrupted, so it may or may not have been sent";
+
+/// Claim a due reminder by moving it from pending to delivering. Only one
+/// caller can ever claim each delivery.
+async fn claim(db: &sqlx::SqlitePool, id: i64) -> Result<Claim> {
+    let claimed = sqlx::query!(
+        "UPDATE reminders SET state = 'delivering', attempts = attempts + 1 WHERE id = $1 AND state = 'pending' RETURNING attempts",
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(claimed) = claimed {
+        return Ok(Claim::Claimed(claimed.attempts));
+    }
+
+    // If it's still delivering, whatever was sending it didn't finish, most
+    // likely because the bot restarted.
+    let interrupted = sqlx::query!(
+        "UPDATE reminders SET state = 'failed', last_error = $1 WHERE id = $2 AND state = 'delivering' AND recurrence IS NULL",
+        INTERRUPTED_ERROR,
+        id
+    )
+    .execute(db)
+    .await?
+    .rows_affected()
+        > 0;
+    if interrupted {
+        return Ok(Claim::Interrupted);
+    }
+
+    let recurring = sqlx::query!(
+        "SELECT id FROM reminders WHERE id = $1 AND state = 'delivering'",
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(if recurring.is_some() {
+        Claim::InterruptedOccurrence
+    } else {
+        Claim::Gone
+    })
+}
+
+/// Give up on the current occurrence of a recurring reminder and move the
+/// series on to remind_at. A failed one-off copy of the occurrence is kept
+/// with the error so admins can see and retry it. Returns the ID of the copy.
+async fn skip_occurrence(
+    db: &sqlx::SqlitePool,
+    id: i64,
+    remind_at: i64,
+    error: &str,
+) -> Result<i64> {
+    let mut tx = db.begin().await?;
 
-pub struct RemindPlugin {}
+    let copy_id = sqlx::query!(
+        "INSERT INTO reminders (channel_id, target_user, target_user_id, private, message, remind_at, created_at, created_by, created_by_id, recurrence, timezone, state, attempts, last_error)
+SELECT channel_id, target_user, target_user_id, private, message, remind_at, created_at, created_by, created_by_id, NULL, timezone, 'failed', attempts, $1 FROM reminders WHERE id = $2",
+        error,
+        id
+    )
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
 
-#[derive(sqlx::FromRow)]
+    sqlx::query!(
+        "UPDATE reminders SET state = 'pending', remind_at = $1, attempts = 0, last_error = NULL WHERE id = $2",
+        remind_at,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(copy_id)
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize)]
 struct Reminder {
     id: i64,
-    channel_id: String,
+
+    // Reminders set in a private message don't have a channel.
+    channel_id: Option<String>,
     target_user: String,
+
+    // Only known when people set reminders for themselves, so only those can
+    // be sent privately.
+    target_user_id: Option<String>,
+    private: bool,
     message: String,
     remind_at: i64,
     created_at: i64,
     created_by: String,
+
+    // Whoever set the reminder can list and cancel it by this, since anyone
+    // can use their nick.
+    created_by_id: Option<String>,
+    recurrence: Option<String>,
+    timezone: String,
 }
 
-fn parse_duration(s: &str) -> Result<Duration> {
-    let s = s.trim().to_lowercase();
-    if s.len() < 2 {
-        return Err(format_err!("Invalid duration format"));
-    }
-
-    let (num_str, unit) = s.split_at(s.len() - 1);
-    let num: u64 = num_str
-        .parse()
-        .map_err(|_| format_err!("Invalid number in duration"))?;
-
-    let seconds = match unit {
-        "s" => num,
-        "m" => num * 60,
-        "h" => num * 60 * 60,
-        "d" => num * 60 * 60 * 24,
-        "w" => num * 60 * 60 * 24 * 7,
-        _ => {
-            return Err(format_err!(
-                "Unknown duration unit '{}'. Use s/m/h/d/w",
-                unit
-            ))
+impl Reminder {
+    async fn get(db: &sqlx::SqlitePool, id: i64) -> Result<Self> {
+        Ok(sqlx::query_as!(
+            Reminder,
+            r#"SELECT id as "id!", channel_id, target_user, target_user_id, private, message, remind_at, created_at, created_by, created_by_id, recurrence, timezone FROM reminders WHERE id = $1"#,
+            id
+        )
+        .fetch_one(db)
+        .await?)
+    }
+
+    /// When a recurring reminder should next go off after its current
+    /// occurrence.
+    fn next_occurrence(&self) -> Result<i64> {
+        let recurrence: Recurrence = self
+            .recurrence
+            .as_deref()
+            .ok_or_else(|| format_err!("Reminder {} doesn't repeat", self.id))?
+            .parse()?;
+        let timezone: chrono_tz::Tz = self
+            .timezone
+            .parse()
+            .map_err(|e| format_err!("Invalid reminder timezone: {}", e))?;
+
+        let now_local = chrono::Utc::now().with_timezone(&timezone);
+        let last = chrono::DateTime::from_timestamp(self.remind_at, 0)
+            .ok_or_else(|| format_err!("Invalid reminder time"))?
+            .with_timezone(&timezone);
+
+        Ok(recurrence.next(&last, &now_local)?.timestamp())
+    }
+
+    /// The text sent when the reminder is delivered, without who it's for.
+    fn text(&self, now: i64) -> String {
+        let when = match &self.recurrence {
+            Some(recurrence) => recurrence.clone(),
+            None => format!("{} ago", format_duration(now - self.created_at)),
+        };
+
+        if self.created_by == self.target_user {
+            format!("Reminder ({}): {}", when, self.message)
+        } else {
+            format!(
+                "Reminder from {} ({}): {}",
+                self.created_by, when, self.message
+            )
         }
-    };
+    }
+}
 
-    Ok(Duration::from_secs(seconds))
+/// Reminders can be set by sending "remind ..." or "!remind ..." in a private
+/// message, since commands are only parsed in channels. Returns the command's
+/// argument, like Event::Command.
+fn parse_private_command(text: &str) -> Option<Option<&str>> {
+    let text = text.trim();
+    let text = text.strip_prefix('!').unwrap_or(text);
+    let (command, arg) = text.split_once(' ').unwrap_or((text, ""));
+
+    if !command.eq_ignore_ascii_case("remind") {
+        return None;
+    }
+
+    let arg = arg.trim();
+    Some(if arg.is_empty() { None } else { Some(arg) })
 }
 
-fn format_duration(secs: i64) -> String {
-    let secs = secs.unsigned_abs();
-    if secs < 60 {
-        format!("{} second{}", secs, if secs == 1 { "" } else { "s" })
-    } else if secs < 3600 {
-        let mins = secs / 60;
-        format!("{} minute{}", mins, if mins == 1 { "" } else { "s" })
-    } else if secs < 86400 {
-        let hours = secs / 3600;
-        format!("{} hour{}", hours, if hours == 1 { "" } else { "s" })
+/// Describe when a reminder will happen, like "in 5 minutes" or "at Fri Oct
+/// 16 17:30 CDT (in 5 hours)".
+fn format_when(when: &When, now: i64) -> String {
+    let duration_text = format_duration(when.at.timestamp() - now);
+    if when.relative {
+        format!("in {}", duration_text)
     } else {
-        let days = secs / 86400;
-        format!("{} day{}", days, if days == 1 { "" } else { "s" })
+        format!("at {} (in {})", when.at.format(TIME_FORMAT), duration_text)
     }
 }
 
 impl RemindPlugin {
-    fn new() -> Self {
-        RemindPlugin {}
+    fn new(config: RemindConfig) -> Self {
+        RemindPlugin { config }
     }
 
     async fn handle_list(&self, ctx: &Arc<Context>) -> Result<()> {
-        let sender = ctx
-            .sender()
-            .ok_or_else(|| format_err!("Could not determine sender"))?;
+        // Reminders are matched by user ID rather than nick, so whoever
+        // takes someone's nick can't see them.
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
 
         let db = ctx.get_db();
 
         let reminders: Vec<Reminder> = sqlx::query_as!(
             Reminder,
-            r#"SELECT id as "id!", channel_id, target_user, message, remind_at, created_at, created_by
+            r#"SELECT id as "id!", channel_id, target_user, target_user_id, private, message, remind_at, created_at, created_by, created_by_id, recurrence, timezone
                FROM reminders
-               WHERE created_by = $1 OR target_user = $1
+               WHERE (created_by_id = $1 OR target_user_id = $1) AND state IN ('pending', 'delivering')
                ORDER BY remind_at ASC
                LIMIT 10"#,
-            sender
+            sender_id
         )
         .fetch_all(&db)
         .await?;
@@ -91,20 +308,31 @@ impl RemindPlugin {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs() as i64;
+        let timezone = ctx.timezone().await?;
 
         let lines: Vec<String> = reminders
             .iter()
             .map(|r| {
-                let time_left = format_duration(r.remind_at - now);
-                let target = if r.target_user == sender {
+                let time_left = match chrono::DateTime::from_timestamp(r.remind_at, 0) {
+                    Some(at) => format!(
+                        "in {} ({})",
+                        format_duration(r.remind_at - now),
+                        at.with_timezone(&timezone).format(TIME_FORMAT)
+                    ),
+                    None => format!("in {}", format_duration(r.remind_at - now)),
+                };
+                let target = if r.target_user_id.as_deref() == Some(sender_id) {
                     "you".to_string()
                 } else {
                     r.target_user.clone()
                 };
-                format!(
-                    "[{}] in {} for {}: \"{}\"",
-                    r.id, time_left, target, r.message
-                )
+                match &r.recurrence {
+                    Some(recurrence) => format!(
+                        "[{}] {} for {}, {}: \"{}\"",
+                        r.id, time_left, target, recurrence, r.message
+                    ),
+                    None => format!("[{}] {} for {}: \"{}\"", r.id, time_left, target, r.message),
+                }
             })
             .collect();
 
@@ -114,9 +342,9 @@ impl RemindPlugin {
     }
 
     async fn handle_cancel(&self, ctx: &Arc<Context>, id_str: &str) -> Result<()> {
-        let sender = ctx
-            .sender()
-            .ok_or_else(|| format_err!("Could not determine sender"))?;
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
 
         let id: i64 = match id_str.trim().parse() {
             Ok(id) => id,
@@ -129,10 +357,14 @@ impl RemindPlugin {
 
         let db = ctx.get_db();
 
+        // Admins can also cancel other people's reminders, like ones which
+        // failed to send.
+        let is_admin = ctx.is_admin();
         let result = sqlx::query!(
-            "DELETE FROM reminders WHERE id = $1 AND (created_by = $2 OR target_user = $2)",
+            "DELETE FROM reminders WHERE id = $1 AND (created_by_id = $2 OR target_user_id = $2 OR $3) AND state != 'delivered'",
             id,
-            sender
+            sender_id,
+            is_admin
         )
         .execute(&db)
         .await?;
@@ -141,49 +373,295 @@ impl RemindPlugin {
             ctx.mention_reply("Reminder not found or you don't have permission to cancel it.")
                 .await?;
         } else {
+            ctx.scheduler()
+                .cancel("remind", &reminder_job_key(id))
+                .await?;
             ctx.mention_reply("Reminder cancelled.").await?;
         }
 
         Ok(())
     }
 
+    async fn handle_failed(&self, ctx: &Arc<Context>) -> Result<()> {
+        let failed = sqlx::query!(
+            r#"SELECT id as "id!", target_user, message, attempts, last_error
+               FROM reminders
+               WHERE state = 'failed'
+               ORDER BY id ASC
+               LIMIT 10"#
+        )
+        .fetch_all(&ctx.get_db())
+        .await?;
+
+        if failed.is_empty() {
+            return ctx.mention_reply("There are no failed reminders.").await;
+        }
+
+        let lines: Vec<String> = failed
+            .iter()
+            .map(|r| {
+                format!(
+                    "[{}] for {} after {} attempt{} ({}): \"{}\"",
+                    r.id,
+                    r.target_user,
+                    r.attempts,
+                    if r.attempts == 1 { "" } else { "s" },
+                    r.last_error.as_deref().unwrap_or("unknown error"),
+                    r.message
+                )
+            })
+            .collect();
+
+        ctx.mention_reply(&lines.join(" | ")).await
+    }
+
+    async fn handle_retry(&self, ctx: &Arc<Context>, id_str: &str) -> Result<()> {
+        let id: i64 = match id_str.trim().parse() {
+            Ok(id) => id,
+            Err(_) => {
+                return ctx
+                    .mention_reply(
+                        "Invalid reminder ID. Use 'remind failed' to see failed reminders.",
+                    )
+                    .await;
+            }
+        };
+
+        let result = sqlx::query!(
+            "UPDATE reminders SET state = 'pending', attempts = 0 WHERE id = $1 AND state = 'failed'",
+            id
+        )
+        .execute(&ctx.get_db())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return ctx.mention_reply("No failed reminder with that ID.").await;
+        }
+
+        ctx.scheduler()
+            .schedule_once("remind", &reminder_job_key(id), scheduler::now(), &id)
+            .await?;
+
+        ctx.mention_reply("Trying that reminder again.").await
+    }
+
+    async fn handle_private(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        // Settings are kept by user ID so they stay with someone if they
+        // change nicks and don't go to whoever takes their old one.
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
+        let storage = ctx.storage("remind").user(sender_id);
+
+        match arg {
+            None => {
+                let private = storage.get("private").await?.unwrap_or(false);
+                ctx.mention_reply(if private {
+                    "Your reminders are sent privately."
+                } else {
+                    "Your reminders are sent to the channel they were set in."
+                })
+                .await
+            }
+            Some("on") => {
+                storage.set("private", &true).await?;
+                ctx.mention_reply("Reminders you set for yourself will now be sent privately.")
+                    .await
+            }
+            Some("off") => {
+                storage.delete("private").await?;
+                ctx.mention_reply(
+                    "Reminders you set for yourself will now be sent to the channel they were set in.",
+                )
+                .await
+            }
+            Some(_) => ctx.mention_reply("Usage: remind private [on|off]").await,
+        }
+    }
+
+    /// Show everything remind keeps for the sender between reminders.
+    async fn handle_settings(&self, ctx: &Arc<Context>) -> Result<()> {
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
+
+        let stored: Vec<(String, serde_json::Value)> =
+            ctx.storage("remind").user(sender_id).list().await?;
+        let settings: Vec<String> = stored
+            .iter()
+            .filter_map(|(key, value)| match key.as_str() {
+                "private" => Some("reminders for yourself are sent privately".to_string()),
+                "last_delivered" => value
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .map(|message| format!("\"{}\" can be snoozed", message)),
+                _ => None,
+            })
+            .collect();
+
+        if settings.is_empty() {
+            return ctx
+                .mention_reply("You haven't changed any reminder settings.")
+                .await;
+        }
+
+        ctx.mention_reply(&format!(
+            "{}.",
+            utils::to_sentence_case(&settings.join(", "))
+        ))
+        .await
+    }
+
+    async fn handle_snooze(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        // The last delivered reminder is kept by user ID, like the private
+        // setting.
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
+        let storage = ctx.storage("remind").user(sender_id);
+
+        let mut reminder: Reminder = match storage.get("last_delivered").await? {
+            Some(reminder) => reminder,
+            None => {
+                ctx.mention_reply("You haven't had any reminders to snooze.")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let now = chrono::Utc::now().with_timezone(&ctx.timezone().await?);
+        let when = match parse_when(arg.unwrap_or(DEFAULT_SNOOZE), &now) {
+            Ok(when) => when,
+            Err(e) => {
+                ctx.mention_reply(&format!("{}", e)).await?;
+                return Ok(());
+            }
+        };
+
+        // A snoozed recurring reminder only comes back once, the series
+        // carries on as normal.
+        reminder.remind_at = when.at.timestamp();
+        reminder.recurrence = None;
+        reminder.timezone = when.at.timezone().name().to_string();
+        self.schedule(ctx, &reminder).await?;
+
+        // Each delivery can only be snoozed once.
+        storage.delete("last_delivered").await?;
+
+        ctx.mention_reply(&format!(
+            "Snoozed {}: \"{}\"",
+            format_when(&when, now.timestamp()),
+            reminder.message
+        ))
+        .await
+    }
+
+    /// Store a new reminder and schedule its delivery, returning its ID. The
+    /// ID on the given reminder is ignored.
+    async fn schedule(&self, ctx: &Context, reminder: &Reminder) -> Result<i64> {
+        let id = sqlx::query!(
+            "INSERT INTO reminders (channel_id, target_user, target_user_id, private, message, remind_at, created_at, created_by, created_by_id, recurrence, timezone) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            reminder.channel_id,
+            reminder.target_user,
+            reminder.target_user_id,
+            reminder.private,
+            reminder.message,
+            reminder.remind_at,
+            reminder.created_at,
+            reminder.created_by,
+            reminder.created_by_id,
+            reminder.recurrence,
+            reminder.timezone
+        )
+        .execute(&ctx.get_db())
+        .await?
+        .last_insert_rowid();
+
+        ctx.scheduler()
+            .schedule_once("remind", &reminder_job_key(id), reminder.remind_at, &id)
+            .await?;
+
+        Ok(id)
+    }
+
     async fn handle_add(
         &self,
         ctx: &Arc<Context>,
         target: &str,
-        duration: std::time::Duration,
+        when: When,
+        recurrence: Option<&Recurrence>,
+        private: Option<bool>,
         message: &str,
     ) -> Result<()> {
         let sender = ctx.sender().unwrap_or("unknown sender");
+        let for_sender = target == sender;
+        let channel_id = ctx.target_channel_id();
+
+        // Reminders set in a private message have nowhere else to go. In a
+        // channel, people can choose each time or set a preference.
+        let private = match (channel_id, private) {
+            (None, _) => true,
+            (Some(_), Some(private)) => private,
+            (Some(_), None) => match ctx.sender_id().filter(|_| for_sender) {
+                Some(sender_id) => ctx
+                    .storage("remind")
+                    .user(sender_id)
+                    .get("private")
+                    .await?
+                    .unwrap_or(false),
+                None => false,
+            },
+        };
 
-        let channel_id = ctx
-            .target_channel_id()
-            .ok_or_else(|| format_err!("Could not determine channel"))?;
+        if private && !for_sender {
+            ctx.mention_reply(if channel_id.is_none() {
+                "Reminders set in a private message can only be for you."
+            } else {
+                "Only reminders for yourself can be sent privately."
+            })
+            .await?;
+            return Ok(());
+        }
+
+        let target_user_id = ctx.sender_id().filter(|_| for_sender);
+        if private && target_user_id.is_none() {
+            return Err(format_err!("Could not determine sender ID"));
+        }
 
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs() as i64;
-        let remind_at = now + duration.as_secs() as i64;
 
-        let db = ctx.get_db();
-
-        sqlx::query!(
-            "INSERT INTO reminders (channel_id, target_user, message, remind_at, created_at, created_by) VALUES ($1, $2, $3, $4, $5, $6)",
-            channel_id,
-            target,
-            message,
-            remind_at,
-            now,
-            sender
-        )
-        .execute(&db)
-        .await?;
+        let reminder = Reminder {
+            id: 0,
+            channel_id: channel_id.map(str::to_string),
+            target_user: target.to_string(),
+            target_user_id: target_user_id.map(str::to_string),
+            private,
+            message: message.to_string(),
+            remind_at: when.at.timestamp(),
+            created_at: now,
+            created_by: sender.to_string(),
+            created_by_id: ctx.sender_id().map(str::to_string),
+            recurrence: recurrence.map(|recurrence| recurrence.to_string()),
+            timezone: when.at.timezone().name().to_string(),
+        };
+        self.schedule(ctx, &reminder).await?;
 
-        let duration_text = format_duration(duration.as_secs() as i64);
+        let time_text = format_when(&when, now);
+        let time_text = match recurrence {
+            Some(recurrence) => format!("{}, starting {}", recurrence, time_text),
+            None => time_text,
+        };
         ctx.mention_reply(&format!(
-            "I'll remind {} in {}: \"{}\"",
-            if target == sender { "you" } else { target },
-            duration_text,
+            "I'll remind {}{} {}: \"{}\"",
+            if for_sender { "you" } else { target },
+            if private && channel_id.is_some() {
+                " privately"
+            } else {
+                ""
+            },
+            time_text,
             message
         ))
         .await?;
@@ -196,7 +674,7 @@ impl RemindPlugin {
             Some(a) => a,
             None => {
                 ctx.mention_reply(
-                    "Usage: remind <user|me> <time> <message> | remind list | remind cancel <id>",
+                    "Usage: remind <user|me> [privately|publicly] [every] <time> <message> | remind list | remind cancel <id> | remind snooze [time] | remind private [on|off] | remind settings",
                 )
                 .await?;
                 return Ok(());
@@ -205,13 +683,10 @@ impl RemindPlugin {
 
         let mut parts = arg.splitn(2, ' ');
         let first_arg = parts.next().unwrap_or("");
-        let rest = parts.next();
+        let rest = parts.next().map(str::trim).filter(|rest| !rest.is_empty());
 
         match first_arg {
-            "list" => {
-                self.handle_list(ctx).await?;
-                return Ok(());
-            }
+            "list" => self.handle_list(ctx).await,
             "cancel" => {
                 let id_str = match rest {
                     None => {
@@ -221,100 +696,306 @@ impl RemindPlugin {
                     Some(id_str) => id_str,
                 };
 
-                self.handle_cancel(ctx, id_str).await?;
-                return Ok(());
+                self.handle_cancel(ctx, id_str).await
+            }
+            "snooze" => self.handle_snooze(ctx, rest).await,
+            "failed" | "retry" if !ctx.is_admin() => {
+                ctx.mention_reply(&format!("only admins can use remind {}", first_arg))
+                    .await
             }
+            "failed" => self.handle_failed(ctx).await,
+            "retry" => match rest {
+                None => ctx.mention_reply("Usage: remind retry <id>").await,
+                Some(id_str) => self.handle_retry(ctx, id_str).await,
+            },
+            "private" => self.handle_private(ctx, rest).await,
+            "settings" => self.handle_settings(ctx).await,
             arg => {
-                let (duration, message) = match rest.unwrap_or("").split_once(' ') {
-                    None => {
-                        ctx.mention_reply("Usage: remind <user|me> <time> <message>")
-                            .await?;
-                        return Ok(());
-                    }
-                    Some((time, message)) => (time, message),
-                };
-
                 let target = if arg.eq_ignore_ascii_case("me") {
                     ctx.sender().unwrap_or("unknown sender")
                 } else {
                     arg
                 };
 
-                let duration = match parse_duration(duration) {
-                    Err(e) => {
+                let (private, rest) =
+                    match rest.map(|rest| rest.split_once(' ').unwrap_or((rest, ""))) {
+                        Some(("privately", rest)) => (Some(true), Some(rest)),
+                        Some(("publicly", rest)) => (Some(false), Some(rest)),
+                        _ => (None, rest),
+                    };
+
+                let now = chrono::Utc::now().with_timezone(&ctx.timezone().await?);
+
+                if let Some(rest) = rest.filter(|rest| rest.to_lowercase().starts_with("every ")) {
+                    let (recurrence, message) = match parse_recurrence_prefix(rest) {
+                        Ok((_, "")) => {
+                            ctx.mention_reply("Usage: remind <user|me> every <time> <message>")
+                                .await?;
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            ctx.mention_reply(&format!("{}", e)).await?;
+                            return Ok(());
+                        }
+                        Ok(parsed) => parsed,
+                    };
+
+                    let when = When {
+                        at: recurrence.next(&now, &now)?,
+                        relative: matches!(recurrence, Recurrence::Interval(_)),
+                    };
+
+                    return self
+                        .handle_add(ctx, target, when, Some(&recurrence), private, message)
+                        .await;
+                }
+
+                let (when, message) = match rest.map(|rest| parse_when_prefix(rest, &now)) {
+                    None | Some(Ok((_, ""))) => {
+                        ctx.mention_reply("Usage: remind <user|me> <time> <message>")
+                            .await?;
+                        return Ok(());
+                    }
+                    Some(Err(e)) => {
                         ctx.mention_reply(&format!("{}", e)).await?;
                         return Ok(());
                     }
-                    Ok(d) => d,
+                    Some(Ok(parsed)) => parsed,
                 };
 
-                self.handle_add(ctx, target, duration, message).await?;
+                self.handle_add(ctx, target, when, None, private, message)
+                    .await
+            }
+        }
+    }
 
-                return Ok(());
+    async fn deliver_reminder(&self, bot: &Client, job: &Job) -> Result<()> {
+        let id: i64 = job.payload()?;
+        let now = scheduler::now();
+        let db = bot.get_db();
+
+        let attempts = match claim(&db, id).await? {
+            Claim::Claimed(attempts) => attempts,
+            Claim::Interrupted => {
+                warn!(
+                    "Delivery of reminder {} was interrupted, so it won't be retried",
+                    id
+                );
+                return bot.scheduler().complete(job).await;
             }
+            Claim::InterruptedOccurrence => {
+                let reminder = Reminder::get(&db, id).await?;
+                let remind_at = reminder.next_occurrence()?;
+                let copy_id = skip_occurrence(&db, id, remind_at, INTERRUPTED_ERROR).await?;
+                warn!(
+                    "Delivery of reminder {} was interrupted, so that occurrence was kept as failed reminder {}",
+                    id, copy_id
+                );
+
+                return bot
+                    .scheduler()
+                    .schedule_once("remind", &reminder_job_key(id), remind_at, &id)
+                    .await;
+            }
+            Claim::Gone => return bot.scheduler().complete(job).await,
+        };
+
+        let reminder = Reminder::get(&db, id).await?;
+
+        match self.send(bot, &reminder, now).await {
+            Ok(()) => self.mark_delivered(bot, job, &reminder, now).await,
+            Err(err) => self.mark_failed(bot, job, &reminder, attempts, &err).await,
         }
     }
 
-    async fn check_due_reminders(&self, bot: &Client) -> Result<()> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs() as i64;
+    async fn send(&self, bot: &Client, reminder: &Reminder, now: i64) -> Result<()> {
+        let text = reminder.text(now);
+        match (&reminder.target_user_id, &reminder.channel_id) {
+            (Some(user_id), _) if reminder.private => bot.send_private_message(user_id, text).await,
+            (_, Some(channel_id)) => {
+                bot.send_message(channel_id, format!("{}: {}", reminder.target_user, text))
+                    .await
+            }
+            _ => Err(format_err!("Reminder has nowhere to be sent")),
+        }
+    }
 
+    async fn mark_delivered(
+        &self,
+        bot: &Client,
+        job: &Job,
+        reminder: &Reminder,
+        now: i64,
+    ) -> Result<()> {
         let db = bot.get_db();
 
-        let reminders: Vec<Reminder> = sqlx::query_as!(
-            Reminder,
-            r#"SELECT id as "id!", channel_id, target_user, message, remind_at, created_at, created_by FROM reminders WHERE remind_at <= $1"#,
-            now
+        if reminder.recurrence.is_some() {
+            self.reschedule(bot, reminder, now).await?;
+        } else {
+            sqlx::query!(
+                "UPDATE reminders SET state = 'delivered', delivered_at = $1, last_error = NULL WHERE id = $2",
+                now,
+                reminder.id
+            )
+            .execute(&db)
+            .await?;
+
+            bot.scheduler().complete(job).await?;
+        }
+
+        // Delivered reminders are only kept around for a while, so this keeps
+        // them from piling up forever.
+        let delivered_before = now - DELIVERED_RETENTION.as_secs() as i64;
+        sqlx::query!(
+            "DELETE FROM reminders WHERE state = 'delivered' AND delivered_at <= $1",
+            delivered_before
         )
-        .fetch_all(&db)
+        .execute(&db)
         .await?;
 
-        for reminder in reminders {
-            let age = format_duration(now - reminder.created_at);
-            let msg = if reminder.created_by == reminder.target_user {
-                format!(
-                    "{}: Reminder ({} ago): {}",
-                    reminder.target_user, age, reminder.message
-                )
-            } else {
-                format!(
-                    "{}: Reminder from {} ({} ago): {}",
-                    reminder.target_user, reminder.created_by, age, reminder.message
+        // Only reminders for someone whose user ID is known can be snoozed,
+        // since that's who snooze looks them up for.
+        match &reminder.target_user_id {
+            Some(user_id) => {
+                bot.storage("remind")
+                    .user(user_id)
+                    .set_with_ttl("last_delivered", reminder, SNOOZE_WINDOW)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Record a failed delivery, either trying again later or giving up once
+    /// the reminder is out of attempts.
+    async fn mark_failed(
+        &self,
+        bot: &Client,
+        job: &Job,
+        reminder: &Reminder,
+        attempts: i64,
+        err: &anyhow::Error,
+    ) -> Result<()> {
+        let db = bot.get_db();
+        let error = format!("{}", err);
+
+        if attempts >= self.config.max_attempts && reminder.recurrence.is_some() {
+            // Like an interrupted delivery, only this occurrence is given
+            // up on and the series carries on.
+            let remind_at = reminder.next_occurrence()?;
+            let copy_id = skip_occurrence(&db, reminder.id, remind_at, &error).await?;
+            warn!(
+                "Giving up on an occurrence of reminder {} after {} attempts, kept as failed reminder {}: {}",
+                reminder.id, attempts, copy_id, error
+            );
+
+            return bot
+                .scheduler()
+                .schedule_once(
+                    "remind",
+                    &reminder_job_key(reminder.id),
+                    remind_at,
+                    &reminder.id,
                 )
-            };
+                .await;
+        }
 
-            if let Err(e) = bot.send_message(&reminder.channel_id, &msg).await {
-                error!("Failed to send reminder: {}", e);
-                continue;
-            }
+        if attempts >= self.config.max_attempts {
+            warn!(
+                "Giving up on reminder {} after {} attempts: {}",
+                reminder.id, attempts, error
+            );
 
-            sqlx::query!("DELETE FROM reminders WHERE id = $1", reminder.id)
-                .execute(&db)
-                .await?;
+            sqlx::query!(
+                "UPDATE reminders SET state = 'failed', last_error = $1 WHERE id = $2",
+                error,
+                reminder.id
+            )
+            .execute(&db)
+            .await?;
+
+            return bot.scheduler().complete(job).await;
         }
 
-        Ok(())
+        let retry_at = scheduler::now() + self.config.retry_delay(attempts);
+        warn!(
+            "Failed to send reminder {} (attempt {}), retrying at {}: {}",
+            reminder.id, attempts, retry_at, error
+        );
+
+        sqlx::query!(
+            "UPDATE reminders SET state = 'pending', last_error = $1 WHERE id = $2",
+            error,
+            reminder.id
+        )
+        .execute(&db)
+        .await?;
+
+        // Scheduling the same key again moves the existing job rather than
+        // adding another one.
+        bot.scheduler()
+            .schedule_once(
+                "remind",
+                &reminder_job_key(reminder.id),
+                retry_at,
+                &reminder.id,
+            )
+            .await
+    }
+
+    /// Move a recurring reminder on to its next occurrence.
+    async fn reschedule(&self, bot: &Client, reminder: &Reminder, now: i64) -> Result<()> {
+        let remind_at = reminder.next_occurrence()?;
+
+        sqlx::query!(
+            "UPDATE reminders SET state = 'pending', remind_at = $1, attempts = 0, last_error = NULL, delivered_at = $2 WHERE id = $3",
+            remind_at,
+            now,
+            reminder.id
+        )
+        .execute(&bot.get_db())
+        .await?;
+
+        // Scheduling the same key again moves the existing job rather than
+        // adding another one.
+        bot.scheduler()
+            .schedule_once(
+                "remind",
+                &reminder_job_key(reminder.id),
+                remind_at,
+                &reminder.id,
+            )
+            .await
     }
 }
 
+fn reminder_job_key(id: i64) -> String {
+    format!("reminder:{}", id)
+}
+
 #[async_trait]
 impl Plugin for RemindPlugin {
     fn new_from_env() -> Result<Self> {
-        Ok(RemindPlugin::new())
+        Ok(RemindPlugin::new(RemindConfig::from_env()?))
     }
 
     fn command_metadata(&self) -> Vec<CommandMetadata> {
         vec![CommandMetadata {
             name: "remind".to_string(),
-            short_help: "usage: remind <user|me> <time> <message> | list | cancel <id>".to_string(),
-            full_help: "Set a reminder. Time format: 30s, 5m, 2h, 1d, 1w. Use 'remind list' to see pending reminders, 'remind cancel <id>' to cancel one.".to_string(),
+            short_help: "usage: remind <user|me> [privately|publicly] [every] <time> <message> | list | cancel <id> | snooze [time] | private [on|off] | settings".to_string(),
+            full_help: "Set a reminder. Times can be durations like 30s, 5m or 1h30m, or times like 17:30, tomorrow at 9am, friday 2pm or dec 25. \
+Reminders can repeat, like every 30m, every day at 9am, every weekday at 9:55, every mon and thu at 14:00 or every month on the 1st. \
+Use 'remind list' to see pending reminders, 'remind cancel <id>' to cancel one, which also stops a repeating reminder. \
+'remind snooze [time]' brings back the last reminder you got, 10 minutes later by default. \
+Reminders for yourself can be sent privately, either each time with 'remind me privately ...' or always with 'remind private on'. 'remind settings' shows what's kept for you. \
+Reminders can also be set by sending 'remind ...' to the bot in a private message. \
+admins can see reminders which couldn't be sent with 'remind failed' and try them again with 'remind retry <id>'.".to_string(),
         }]
     }
 
     async fn run(self, bot: Arc<Client>) -> Result<()> {
         let mut stream = bot.subscribe();
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let mut jobs = bot.scheduler().subscribe("remind");
 
         loop {
             tokio::select! {
@@ -322,13 +1003,17 @@ impl Plugin for RemindPlugin {
                     let ctx = result?;
                     let res = match ctx.as_event() {
                         Ok(Event::Command("remind", arg)) => self.handle_remind(&ctx, arg).await,
+                        Ok(Event::PrivateMessage(_, text)) => match parse_private_command(text) {
+                            Some(arg) => self.handle_remind(&ctx, arg).await,
+                            None => Ok(()),
+                        },
                         _ => Ok(()),
                     };
                     crate::check_err(&ctx, res).await;
                 }
-                _ = interval.tick() => {
-                    if let Err(e) = self.check_due_reminders(&bot).await {
-                        error!("Error checking reminders: {}", e);
+                Some(job) = jobs.recv() => {
+                    if let Err(e) = self.deliver_reminder(&bot, &job).await {
+                        error!("Failed to send reminder: {}", e);
                     }
                 }
             }
@@ -339,66 +1024,216 @@ impl Plugin for RemindPlugin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing;
 
     #[test]
-    fn test_parse_duration() {
-        // Valid cases: (input, expected_seconds)
-        let valid_cases = [
-            ("5s", 5),
-            ("90s", 90), // More than 1 minute
-            ("1m", 60),
-            ("5m", 300),
-            ("90m", 5400), // More than 1 hour
-            ("2h", 7200),
-            ("25h", 90000), // More than 1 day
-            ("1d", 86400),
-            ("1w", 604800),
-            // Case insensitive and whitespace
-            ("5M", 300),
-            (" 2h ", 7200),
+    fn test_parse_private_command() {
+        let cases = [
+            ("remind me 5m tea", Some(Some("me 5m tea"))),
+            ("!remind list", Some(Some("list"))),
+            ("  Remind   snooze ", Some(Some("snooze"))),
+            ("remind", Some(None)),
+            ("!remind ", Some(None)),
+            ("reminder me 5m tea", None),
+            ("please remind me", None),
+            ("", None),
         ];
 
-        for (input, expected) in valid_cases {
+        for (input, expected) in cases {
             assert_eq!(
-                parse_duration(input).unwrap(),
-                Duration::from_secs(expected),
+                parse_private_command(input),
+                expected,
                 "Failed parsing '{}'",
                 input
             );
         }
+    }
 
-        // Invalid cases
-        let invalid_cases = ["", "x", "5", "xm", "5x", "-5m"];
-        for input in invalid_cases {
-            assert!(
-                parse_duration(input).is_err(),
-                "Expected '{}' to fail parsing",
-                input
-            );
-        }
+    #[test]
+    fn test_reminder_text() {
+        let mut reminder = Reminder {
+            id: 1,
+            channel_id: None,
+            target_user: "alice".to_string(),
+            target_user_id: Some("alice-id".to_string()),
+            private: true,
+            message: "stretch".to_string(),
+            remind_at: 1000,
+            created_at: 400,
+            created_by: "alice".to_string(),
+            created_by_id: Some("alice-id".to_string()),
+            recurrence: None,
+            timezone: "UTC".to_string(),
+        };
+        assert_eq!(reminder.text(1000), "Reminder (10 minutes ago): stretch");
+
+        reminder.created_by = "bob".to_string();
+        assert_eq!(
+            reminder.text(1000),
+            "Reminder from bob (10 minutes ago): stretch"
+        );
+
+        reminder.recurrence = Some("every weekday at 09:55".to_string());
+        assert_eq!(
+            reminder.text(1000),
+            "Reminder from bob (every weekday at 09:55): stretch"
+        );
     }
 
     #[test]
-    fn test_format_duration() {
+    fn test_retry_delay() {
+        let config = RemindConfig {
+            max_attempts: 10,
+            retry_secs: 30,
+        };
+
         let cases = [
-            (1, "1 second"),
-            (5, "5 seconds"),
-            (60, "1 minute"),
-            (300, "5 minutes"),
-            (3600, "1 hour"),
-            (7200, "2 hours"),
-            (86400, "1 day"),
-            (172800, "2 days"),
-            (-60, "1 minute"), // negative values use unsigned_abs
+            (0, 30),
+            (1, 30),
+            (2, 60),
+            (3, 120),
+            (7, 1920),
+            (8, 3600),
+            (100, 3600),
         ];
-
-        for (input, expected) in cases {
+        for (attempts, expected) in cases {
             assert_eq!(
-                format_duration(input),
+                config.retry_delay(attempts),
                 expected,
-                "Failed formatting {}",
-                input
+                "Wrong delay after {} attempts",
+                attempts
             );
         }
     }
+
+    #[tokio::test]
+    async fn test_claim() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let id = sqlx::query!(
+            "INSERT INTO reminders (channel_id, target_user, message, remind_at, created_at, created_by) VALUES ('#chan', 'alice', 'stretch', 100, 0, 'alice')"
+        )
+        .execute(&db)
+        .await?
+        .last_insert_rowid();
+
+        // Only the first claim gets to send it.
+        assert_eq!(claim(&db, id).await?, Claim::Claimed(1));
+
+        // If it's never marked as sent or failed, the next claim assumes the
+        // first was interrupted and gives up on it rather than sending it
+        // again.
+        assert_eq!(claim(&db, id).await?, Claim::Interrupted);
+        let state = sqlx::query!("SELECT state FROM reminders WHERE id = $1", id)
+            .fetch_one(&db)
+            .await?
+            .state;
+        assert_eq!(state, "failed");
+        assert_eq!(claim(&db, id).await?, Claim::Gone);
+
+        // Retrying puts it back, and attempts keep counting up while it's
+        // pending.
+        sqlx::query!(
+            "UPDATE reminders SET state = 'pending', attempts = 0 WHERE id = $1",
+            id
+        )
+        .execute(&db)
+        .await?;
+        assert_eq!(claim(&db, id).await?, Claim::Claimed(1));
+        sqlx::query!("UPDATE reminders SET state = 'pending' WHERE id = $1", id)
+            .execute(&db)
+            .await?;
+        assert_eq!(claim(&db, id).await?, Claim::Claimed(2));
+
+        // Delivered and cancelled reminders can't be claimed.
+        sqlx::query!("UPDATE reminders SET state = 'delivered' WHERE id = $1", id)
+            .execute(&db)
+            .await?;
+        assert_eq!(claim(&db, id).await?, Claim::Gone);
+        assert_eq!(claim(&db, id + 1).await?, Claim::Gone);
+
+        // An interrupted occurrence of a recurring reminder is left for the
+        // caller, which keeps a failed copy and moves the series on.
+        let id = sqlx::query!(
+            "INSERT INTO reminders (channel_id, target_user, message, remind_at, created_at, created_by, recurrence) VALUES ('#chan', 'alice', 'standup', 100, 0, 'alice', 'every day at 09:00')"
+        )
+        .execute(&db)
+        .await?
+        .last_insert_rowid();
+        assert_eq!(claim(&db, id).await?, Claim::Claimed(1));
+        assert_eq!(claim(&db, id).await?, Claim::InterruptedOccurrence);
+
+        let copy_id = skip_occurrence(&db, id, 200, INTERRUPTED_ERROR).await?;
+        let series = sqlx::query!(
+            "SELECT state, remind_at, attempts FROM reminders WHERE id = $1",
+            id
+        )
+        .fetch_one(&db)
+        .await?;
+        assert_eq!(
+            (series.state.as_str(), series.remind_at, series.attempts),
+            ("pending", 200, 0)
+        );
+
+        let copy = sqlx::query!(
+            "SELECT state, remind_at, recurrence, message FROM reminders WHERE id = $1",
+            copy_id
+        )
+        .fetch_one(&db)
+        .await?;
+        assert_eq!(
+            (copy.state.as_str(), copy.remind_at, copy.message.as_str()),
+            ("failed", 100, "standup")
+        );
+        assert_eq!(copy.recurrence, None);
+
+        assert_eq!(claim(&db, id).await?, Claim::Claimed(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_occurrence() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        // A recurring reminder which has run out of attempts only gives up
+        // on this occurrence, keeping the real error on the copy.
+        let id = sqlx::query!(
+            "INSERT INTO reminders (channel_id, target_user, message, remind_at, created_at, created_by, created_by_id, recurrence, state, attempts, last_error) VALUES ('#chan', 'alice', 'standup', 100, 0, 'alice', 'u-alice', 'every day at 09:00', 'delivering', 5, 'timed out')"
+        )
+        .execute(&db)
+        .await?
+        .last_insert_rowid();
+
+        let copy_id = skip_occurrence(&db, id, 200, "connection refused").await?;
+
+        let copy = sqlx::query!(
+            "SELECT state, remind_at, attempts, last_error, created_by_id, recurrence FROM reminders WHERE id = $1",
+            copy_id
+        )
+        .fetch_one(&db)
+        .await?;
+        assert_eq!(
+            (copy.state.as_str(), copy.remind_at, copy.attempts),
+            ("failed", 100, 5)
+        );
+        assert_eq!(copy.last_error.as_deref(), Some("connection refused"));
+        assert_eq!(copy.created_by_id.as_deref(), Some("u-alice"));
+        assert_eq!(copy.recurrence, None);
+
+        let series = sqlx::query!(
+            "SELECT state, remind_at, attempts, last_error, recurrence FROM reminders WHERE id = $1",
+            id
+        )
+        .fetch_one(&db)
+        .await?;
+        assert_eq!(
+            (series.state.as_str(), series.remind_at, series.attempts),
+            ("pending", 200, 0)
+        );
+        assert_eq!(series.last_error, None);
+        assert_eq!(series.recurrence.as_deref(), Some("every day at 09:00"));
+
+        Ok(())
+    }
 }
@@ -1,10 +1,7 @@
 use crate::prelude::*;
 use rand::seq::SliceRandom;
-use tokio::sync::Mutex;
 
-pub struct RiddlePlugin {
-    riddle_answers: Mutex<HashMap<String, &'static str>>,
-}
+pub struct RiddlePlugin;
 
 const RIDDLES: &[(&str, &str)] = &[
     // The Hobbit or There and Back Again by J.R.R. Tolkien - Chapter 5: Riddles in the Dark
@@ -86,16 +83,16 @@ const RIDDLES: &[(&str, &str)] = &[
 
 impl RiddlePlugin {
     async fn handle_riddle_ask(&self, ctx: &Arc<Context>) -> Result<()> {
-        let target = ctx.sender().unwrap_or_else(|| "someone");
+        let target = ctx.sender().unwrap_or("someone");
         let riddle = RIDDLES.choose(&mut rand::thread_rng()).unwrap();
         ctx.action_reply(&format!("asks {}: {}", target, riddle.0))
             .await?;
 
         if let Some(channel_id) = ctx.target_channel_id() {
-            self.riddle_answers
-                .lock()
-                .await
-                .insert(String::from(channel_id), riddle.1);
+            ctx.storage("riddle")
+                .channel(channel_id)
+                .set("answer", &riddle.1)
+                .await?;
         }
 
         Ok(())
@@ -103,11 +100,14 @@ impl RiddlePlugin {
 
     async fn handle_riddle_answer(&self, ctx: &Arc<Context>) -> Result<()> {
         if let Some(channel_id) = ctx.target_channel_id() {
-            if let Some(previous_answer) = self.riddle_answers.lock().await.remove(channel_id) {
+            let storage = ctx.storage("riddle").channel(channel_id);
+
+            if let Some(previous_answer) = storage.get::<String>("answer").await? {
+                storage.delete("answer").await?;
                 ctx.action_reply(&format!("answers: {}", previous_answer))
                     .await?;
             } else {
-                ctx.action_reply(&format!("cannot remember...")).await?;
+                ctx.action_reply("cannot remember...").await?;
             }
         }
 
@@ -118,9 +118,7 @@ impl RiddlePlugin {
 #[async_trait]
 impl Plugin for RiddlePlugin {
     fn new_from_env() -> Result<Self> {
-        Ok(RiddlePlugin {
-            riddle_answers: Default::default(),
-        })
+        Ok(RiddlePlugin)
     }
 
     fn command_metadata(&self) -> Vec<CommandMetadata> {
@@ -1,5 +1,5 @@
-use std::error::Error;
 use std::fmt::Write;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -7,18 +7,36 @@ use scryfall::{search::Search, Card};
 use url::Url;
 
 use crate::prelude::*;
+use crate::utils::cache::Cache;
 
 lazy_static! {
     static ref SCRYFALL_RE: Regex = Regex::new(r#"\[\[(.+?)\]\]"#).expect("invalid scryfall regex");
 }
 
-pub struct ScryfallPlugin;
+pub struct ScryfallPlugin {
+    api_base: String,
+    http: utils::http::Client,
+    cache: Cache<Card>,
+}
 
+const SCRYFALL_API_BASE: &str = "https://api.scryfall.com";
 const SCRYFALL_SEARCH_URL: &str = "https://scryfall.com/search";
 
+// Card data changes rarely, mostly when prices update once a day.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const CACHE_SIZE: usize = 512;
+
 impl ScryfallPlugin {
     pub fn new() -> Self {
-        ScryfallPlugin
+        Self::with_api_base(SCRYFALL_API_BASE.to_string())
+    }
+
+    pub fn with_api_base(api_base: String) -> Self {
+        ScryfallPlugin {
+            api_base,
+            http: utils::http::client(),
+            cache: Cache::new("scryfall", CACHE_SIZE, CACHE_TTL),
+        }
     }
 }
 
@@ -58,10 +76,35 @@ fn scryfall_error_string(err: &scryfall::Error) -> String {
 }
 
 impl ScryfallPlugin {
+    // The scryfall crate always uses its own HTTP client, so exact name
+    // lookups, which happen on every [[card]] mention, go through ours
+    // instead. Searches still use the crate for its pagination support.
+    async fn named(&self, name: &str) -> Result<Card> {
+        self.cache
+            .get_or_fetch(&name.to_lowercase(), || self.named_uncached(name))
+            .await
+    }
+
+    async fn named_uncached(&self, name: &str) -> Result<Card> {
+        let resp = self
+            .http
+            .get(
+                &format!("{}/cards/named", self.api_base),
+                &[("exact", name)],
+            )
+            .await?;
+
+        if !resp.status().is_success() {
+            let err: scryfall::error::ScryfallError = resp.json().await?;
+            return Err(format_err!("{}", err.details));
+        }
+
+        Ok(resp.json().await?)
+    }
+
     async fn handle_scryfall(&self, ctx: &Arc<Context>, arg: &str) -> Result<()> {
-        let card_iter = Card::search(arg).await.map_err(|err| {
-            println!("{}", scryfall_error_string(&err));
-            err
+        let card_iter = Card::search(arg).await.inspect_err(|err| {
+            println!("{}", scryfall_error_string(err));
         })?;
 
         let (n, _) = card_iter.size_hint();
@@ -94,7 +137,7 @@ impl ScryfallPlugin {
 
         // Loop through all captures, adding them to the output.
         for capture in captures {
-            match Card::named(&capture[1]).await {
+            match self.named(&capture[1]).await {
                 Ok(card) => {
                     let card_uri = card.scryfall_uri;
                     let image_uri = card.image_uris.and_then(|uris| uris.png);
@@ -138,6 +181,10 @@ impl Plugin for ScryfallPlugin {
     }
 
     async fn run(self, bot: Arc<Client>) -> Result<()> {
+        if utils::cache::persistence_enabled()? {
+            self.cache.persist(bot.get_db());
+        }
+
         let mut stream = bot.subscribe();
 
         while let Ok(ctx) = stream.recv().await {
@@ -158,3 +205,29 @@ impl Plugin for ScryfallPlugin {
         Err(format_err!("scryfall plugin lagged"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttp;
+
+    #[tokio::test]
+    async fn test_named_not_found() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(
+            404,
+            r#"{"object": "error", "status": 404, "code": "not_found", "details": "No cards found matching \"Llightning Bolt\""}"#,
+        )])
+        .await?;
+
+        let plugin = ScryfallPlugin::with_api_base(server.url(""));
+        let err = plugin.named("Llightning Bolt").await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "No cards found matching \"Llightning Bolt\""
+        );
+        assert!(server.requests()[0].starts_with("GET /cards/named?exact=Llightning+Bolt "));
+
+        Ok(())
+    }
+}
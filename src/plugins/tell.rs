@@ -0,0 +1,392 @@
+use crate::prelude::*;
+use crate::scheduler::now;
+use crate::utils::when::format_duration;
+
+pub struct TellPlugin {
+    config: TellConfig,
+}
+
+#[derive(Debug, Clone)]
+struct TellConfig {
+    // How many messages can be waiting for one person at a time.
+    max_per_recipient: i64,
+}
+
+impl Default for TellConfig {
+    fn default() -> Self {
+        TellConfig {
+            max_per_recipient: 10,
+        }
+    }
+}
+
+impl TellConfig {
+    fn from_env() -> Result<Self> {
+        let default = TellConfig::default();
+
+        let config = TellConfig {
+            max_per_recipient: utils::env_var_or(
+                "TELL_MAX_PER_RECIPIENT",
+                default.max_per_recipient,
+            )?,
+        };
+
+        if config.max_per_recipient < 1 {
+            return Err(format_err!("$TELL_MAX_PER_RECIPIENT must be at least 1"));
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Tell {
+    id: i64,
+    recipient: String,
+    sender: String,
+
+    // Where the message was left, which is None for private messages.
+    channel_id: Option<String>,
+    message: String,
+    created_at: i64,
+}
+
+impl Tell {
+    /// Leave a message, returning its ID, or None if the recipient already
+    /// has as many messages waiting as they're allowed.
+    async fn insert(
+        conn: &sqlx::SqlitePool,
+        recipient: &str,
+        sender: &str,
+        channel_id: Option<&str>,
+        message: &str,
+        limit: i64,
+    ) -> Result<Option<i64>> {
+        let created_at = now();
+
+        let mut tx = conn.begin().await?;
+
+        let waiting = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM tells WHERE recipient = $1;"#,
+            recipient
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .count;
+
+        if waiting >= limit {
+            return Ok(None);
+        }
+
+        let id = sqlx::query!(
+            "INSERT INTO tells (recipient, sender, channel_id, message, created_at)
+VALUES ($1, $2, $3, $4, $5);",
+            recipient,
+            sender,
+            channel_id,
+            message,
+            created_at,
+        )
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+        tx.commit().await?;
+
+        Ok(Some(id))
+    }
+
+    /// Messages waiting for someone, oldest first.
+    async fn waiting_for(conn: &sqlx::SqlitePool, recipient: &str) -> Result<Vec<Tell>> {
+        Ok(sqlx::query_as!(
+            Tell,
+            r#"SELECT id as "id!", recipient, sender, channel_id, message, created_at FROM tells
+WHERE recipient = $1
+ORDER BY id ASC;"#,
+            recipient
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Messages someone left which haven't been delivered yet, oldest first.
+    async fn sent_by(conn: &sqlx::SqlitePool, sender: &str, limit: i64) -> Result<Vec<Tell>> {
+        Ok(sqlx::query_as!(
+            Tell,
+            r#"SELECT id as "id!", recipient, sender, channel_id, message, created_at FROM tells
+WHERE sender = $1
+ORDER BY id ASC
+LIMIT $2;"#,
+            sender,
+            limit
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Remove a message which hasn't been delivered. Only whoever left it can
+    /// cancel it unless any is set. Returns false if there was nothing to
+    /// cancel.
+    async fn cancel(conn: &sqlx::SqlitePool, id: i64, sender: &str, any: bool) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM tells WHERE id = $1 AND (sender = $2 OR $3);",
+            id,
+            sender,
+            any
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(conn: &sqlx::SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!("DELETE FROM tells WHERE id = $1;", id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl TellPlugin {
+    fn new(config: TellConfig) -> Self {
+        TellPlugin { config }
+    }
+
+    async fn handle_tell(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        let arg = match arg {
+            Some(arg) => arg,
+            None => {
+                return ctx
+                    .mention_reply("Usage: tell <nick> <message> | tell list | tell cancel <id>")
+                    .await;
+            }
+        };
+
+        let (first_arg, rest) = arg.split_once(' ').unwrap_or((arg, ""));
+        let rest = rest.trim();
+
+        match first_arg {
+            "list" => self.handle_list(ctx).await,
+            "cancel" => self.handle_cancel(ctx, rest).await,
+            nick => {
+                // People often address the nick like they would in chat.
+                let nick = nick.trim_end_matches([':', ',']);
+                self.handle_add(ctx, nick, rest).await
+            }
+        }
+    }
+
+    async fn handle_add(&self, ctx: &Arc<Context>, recipient: &str, message: &str) -> Result<()> {
+        let sender = ctx
+            .sender()
+            .ok_or_else(|| format_err!("Could not determine sender"))?;
+
+        if message.is_empty() {
+            return ctx.mention_reply("Usage: tell <nick> <message>").await;
+        }
+
+        if recipient.eq_ignore_ascii_case(sender) {
+            return ctx
+                .mention_reply("You can't leave a message for yourself.")
+                .await;
+        }
+
+        let id = Tell::insert(
+            &ctx.get_db(),
+            recipient,
+            sender,
+            ctx.target_channel_id(),
+            message,
+            self.config.max_per_recipient,
+        )
+        .await?;
+
+        match id {
+            Some(id) => {
+                ctx.mention_reply(&format!(
+                    "I'll pass that on to {} when they're next around. [{}]",
+                    recipient, id
+                ))
+                .await
+            }
+            None => {
+                ctx.mention_reply(&format!(
+                    "{} already has {} messages waiting.",
+                    recipient, self.config.max_per_recipient
+                ))
+                .await
+            }
+        }
+    }
+
+    async fn handle_list(&self, ctx: &Arc<Context>) -> Result<()> {
+        let sender = ctx
+            .sender()
+            .ok_or_else(|| format_err!("Could not determine sender"))?;
+
+        let tells = Tell::sent_by(&ctx.get_db(), sender, 10).await?;
+        if tells.is_empty() {
+            return ctx
+                .mention_reply("You have no messages waiting to be delivered.")
+                .await;
+        }
+
+        let now = now();
+        let lines: Vec<String> = tells
+            .iter()
+            .map(|tell| {
+                format!(
+                    "[{}] for {} ({} ago): \"{}\"",
+                    tell.id,
+                    tell.recipient,
+                    format_duration(now - tell.created_at),
+                    tell.message
+                )
+            })
+            .collect();
+
+        ctx.mention_reply(&lines.join(" | ")).await
+    }
+
+    async fn handle_cancel(&self, ctx: &Arc<Context>, id_str: &str) -> Result<()> {
+        let sender = ctx
+            .sender()
+            .ok_or_else(|| format_err!("Could not determine sender"))?;
+
+        let id: i64 = match id_str.parse() {
+            Ok(id) => id,
+            Err(_) => {
+                return ctx
+                    .mention_reply("Usage: tell cancel <id>. Use 'tell list' to see your messages.")
+                    .await;
+            }
+        };
+
+        if Tell::cancel(&ctx.get_db(), id, sender, ctx.is_admin()).await? {
+            ctx.mention_reply("Message cancelled.").await
+        } else {
+            ctx.mention_reply("Message not found or you don't have permission to cancel it.")
+                .await
+        }
+    }
+
+    /// Pass on any messages waiting for whoever just spoke. Messages which
+    /// were left privately are passed on privately too.
+    async fn deliver(&self, bot: &Client, ctx: &Arc<Context>, recipient: &str) -> Result<()> {
+        let db = ctx.get_db();
+        let now = now();
+
+        for tell in Tell::waiting_for(&db, recipient).await? {
+            let text = format!(
+                "{} said {} ago: {}",
+                tell.sender,
+                format_duration(now - tell.created_at),
+                tell.message
+            );
+
+            match (&tell.channel_id, ctx.sender_id()) {
+                (Some(_), _) => ctx.mention_reply(&text).await?,
+                (None, Some(user_id)) => bot.send_private_message(user_id, text).await?,
+
+                // Without an ID there's nowhere private to send it, so it
+                // waits until there is.
+                (None, None) => continue,
+            }
+
+            Tell::delete(&db, tell.id).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin for TellPlugin {
+    fn new_from_env() -> Result<Self> {
+        Ok(TellPlugin::new(TellConfig::from_env()?))
+    }
+
+    fn command_metadata(&self) -> Vec<CommandMetadata> {
+        vec![CommandMetadata {
+            name: "tell".to_string(),
+            short_help: "usage: tell <nick> <message> | list | cancel <id>".to_string(),
+            full_help: "leaves a message for someone, which is passed on the next time they say something in any channel. \
+list shows the messages you've left which haven't been passed on yet and cancel removes one.".to_string(),
+        }]
+    }
+
+    async fn run(self, bot: Arc<Client>) -> Result<()> {
+        let mut stream = bot.subscribe();
+
+        while let Ok(ctx) = stream.recv().await {
+            let res = match ctx.as_event() {
+                Ok(Event::Command("tell", arg)) => self.handle_tell(&ctx, arg).await,
+                Ok(Event::Message(sender, _)) | Ok(Event::Action(sender, _)) => {
+                    self.deliver(&bot, &ctx, sender).await
+                }
+                _ => Ok(()),
+            };
+
+            crate::check_err(&ctx, res).await;
+        }
+
+        Err(format_err!("tell plugin lagged"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_tells() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let first = Tell::insert(&db, "alice", "bob", Some("#chan"), "hello", 2).await?;
+        let second = Tell::insert(&db, "Alice", "carol", None, "hi there", 2).await?;
+        assert!(first.is_some());
+        assert!(second.is_some());
+
+        // Nicks are matched case-insensitively, so both count towards the
+        // limit.
+        assert_eq!(
+            Tell::insert(&db, "ALICE", "dave", None, "one more", 2).await?,
+            None
+        );
+        assert!(Tell::insert(&db, "erin", "dave", None, "hey", 2)
+            .await?
+            .is_some());
+
+        let waiting = Tell::waiting_for(&db, "aLiCe").await?;
+        assert_eq!(
+            waiting
+                .iter()
+                .map(|tell| (tell.sender.as_str(), tell.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("bob", "hello"), ("carol", "hi there")]
+        );
+        assert_eq!(waiting[0].channel_id.as_deref(), Some("#chan"));
+
+        let sent = Tell::sent_by(&db, "dave", 10).await?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].recipient, "erin");
+
+        // Only whoever left a message can cancel it, unless they're allowed
+        // to cancel any.
+        let first = first.unwrap();
+        assert!(!Tell::cancel(&db, first, "carol", false).await?);
+        assert!(Tell::cancel(&db, first, "BOB", false).await?);
+        assert!(!Tell::cancel(&db, first, "bob", false).await?);
+        assert!(Tell::cancel(&db, second.unwrap(), "someone", true).await?);
+
+        assert!(Tell::waiting_for(&db, "alice").await?.is_empty());
+        assert!(Tell::insert(&db, "alice", "bob", None, "again", 2)
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+}
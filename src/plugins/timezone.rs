@@ -0,0 +1,356 @@
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+use crate::prelude::*;
+use crate::utils::when::parse_clock;
+
+// How times are shown, like "Fri 14:03 CDT".
+const TIME_FORMAT: &str = "%a %H:%M %Z";
+
+pub struct TimezonePlugin;
+
+/// Look up a timezone by its name in the tz database, like "America/Chicago",
+/// or by the city at the end of one, like "new york", ignoring case.
+fn find_timezone(name: &str) -> Option<Tz> {
+    let name = name.trim().replace(' ', "_");
+    if name.is_empty() {
+        return None;
+    }
+
+    TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().eq_ignore_ascii_case(&name))
+        .or_else(|| {
+            TZ_VARIANTS.iter().find(|tz| {
+                tz.name()
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|city| city.eq_ignore_ascii_case(&name))
+            })
+        })
+        .copied()
+}
+
+/// Split a conversion like "3pm Chicago in Berlin" into the time, where it's
+/// from and where to show it. Either place may be empty. Returns None if it
+/// doesn't start with a time.
+fn parse_conversion(s: &str) -> Option<(NaiveTime, String, String)> {
+    let tokens: Vec<&str> = s.split_whitespace().collect();
+    let first = tokens.first()?.to_lowercase();
+
+    let (time, rest) = match tokens.get(1).map(|token| token.to_lowercase()) {
+        Some(meridiem) if meridiem == "am" || meridiem == "pm" => {
+            (parse_clock(&first, Some(&meridiem))?, &tokens[2..])
+        }
+        _ => (parse_clock(&first, None)?, &tokens[1..]),
+    };
+
+    let (from, to) = match rest
+        .iter()
+        .rposition(|token| token.eq_ignore_ascii_case("in"))
+    {
+        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+        None => (rest, &[][..]),
+    };
+
+    Some((time, from.join(" "), to.join(" ")))
+}
+
+/// The time in from's timezone today, along with the same moment in to's.
+fn convert(
+    time: NaiveTime,
+    from: Tz,
+    to: Tz,
+    now: &DateTime<Utc>,
+) -> Result<(DateTime<Tz>, DateTime<Tz>)> {
+    let date = now.with_timezone(&from).date_naive();
+    let at = from
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .ok_or_else(|| {
+            format_err!(
+                "{} {} doesn't exist in {}",
+                date,
+                time.format("%H:%M"),
+                from
+            )
+        })?;
+
+    Ok((at, at.with_timezone(&to)))
+}
+
+// A timezone along with how to describe it, like "in Europe/Berlin" or "for
+// bob".
+struct Place {
+    tz: Tz,
+    label: String,
+}
+
+impl TimezonePlugin {
+    fn new() -> Self {
+        TimezonePlugin
+    }
+
+    async fn handle_tz(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        let sender = ctx
+            .sender()
+            .ok_or_else(|| format_err!("Could not determine sender"))?;
+        let sender_id = ctx
+            .sender_id()
+            .ok_or_else(|| format_err!("Could not determine sender ID"))?;
+
+        let (subcommand, rest) = match arg {
+            Some(arg) => arg.split_once(' ').unwrap_or((arg, "")),
+            None => ("", ""),
+        };
+
+        match subcommand {
+            "" => match ctx.user_timezone(sender_id).await? {
+                Some(tz) => {
+                    ctx.mention_reply(&format!(
+                        "Your timezone is {}, where it's {}.",
+                        tz,
+                        chrono::Utc::now().with_timezone(&tz).format(TIME_FORMAT)
+                    ))
+                    .await
+                }
+                None => {
+                    ctx.mention_reply(&format!(
+                        "You haven't set a timezone, so times are in {}. Use 'tz set <zone>', like 'tz set America/Chicago'.",
+                        ctx.default_timezone()
+                    ))
+                    .await
+                }
+            },
+            "set" => match find_timezone(rest) {
+                Some(tz) => {
+                    ctx.set_user_timezone(sender_id, sender, Some(tz)).await?;
+                    ctx.mention_reply(&format!(
+                        "Your timezone is now {}, where it's {}.",
+                        tz,
+                        chrono::Utc::now().with_timezone(&tz).format(TIME_FORMAT)
+                    ))
+                    .await
+                }
+                None if rest.trim().is_empty() => {
+                    ctx.mention_reply("Usage: tz set <zone>, like 'tz set America/Chicago'.")
+                        .await
+                }
+                None => {
+                    ctx.mention_reply(&format!(
+                        "Unknown timezone \"{}\". Use a name like America/Chicago or a city like Berlin.",
+                        rest.trim()
+                    ))
+                    .await
+                }
+            },
+            "unset" => {
+                ctx.set_user_timezone(sender_id, sender, None).await?;
+                ctx.mention_reply(&format!(
+                    "Your timezone has been cleared, so times are in {}.",
+                    ctx.default_timezone()
+                ))
+                .await
+            }
+            _ => {
+                ctx.mention_reply("Usage: tz | tz set <zone> | tz unset")
+                    .await
+            }
+        }
+    }
+
+    /// Find the timezone for someone who has set one, or for a zone or city
+    /// name. An empty name is the sender's own timezone.
+    async fn find_place(&self, ctx: &Arc<Context>, name: &str) -> Result<Option<Place>> {
+        let name = name.trim();
+
+        if name.is_empty() {
+            return Ok(Some(Place {
+                tz: ctx.timezone().await?,
+                label: "for you".to_string(),
+            }));
+        }
+
+        // Nicks come first, otherwise someone called paris could never be
+        // looked up. The full zone name still works for the city.
+        if let Some(tz) = ctx.nick_timezone(name).await? {
+            let label = match find_timezone(name) {
+                Some(city) => format!("for {} (use {} for the city)", name, city),
+                None => format!("for {}", name),
+            };
+
+            return Ok(Some(Place { tz, label }));
+        }
+
+        Ok(find_timezone(name).map(|tz| Place {
+            tz,
+            label: format!("in {}", tz),
+        }))
+    }
+
+    async fn unknown_place(&self, ctx: &Arc<Context>, name: &str) -> Result<()> {
+        ctx.mention_reply(&format!(
+            "I don't know a timezone or city called \"{}\", and nobody by that nick has set their timezone.",
+            name.trim()
+        ))
+        .await
+    }
+
+    async fn handle_time(&self, ctx: &Arc<Context>, arg: Option<&str>) -> Result<()> {
+        let arg = arg.unwrap_or("");
+
+        if let Some((time, from, to)) = parse_conversion(arg) {
+            let from_place = match self.find_place(ctx, &from).await? {
+                Some(place) => place,
+                None => return self.unknown_place(ctx, &from).await,
+            };
+            let to_place = match self.find_place(ctx, &to).await? {
+                Some(place) => place,
+                None => return self.unknown_place(ctx, &to).await,
+            };
+
+            let (at, converted) = convert(time, from_place.tz, to_place.tz, &Utc::now())?;
+
+            return ctx
+                .mention_reply(&format!(
+                    "{} {} is {} {}.",
+                    at.format(TIME_FORMAT),
+                    from_place.label,
+                    converted.format(TIME_FORMAT),
+                    to_place.label
+                ))
+                .await;
+        }
+
+        match self.find_place(ctx, arg).await? {
+            Some(place) => {
+                ctx.mention_reply(&format!(
+                    "It's {} {}.",
+                    Utc::now().with_timezone(&place.tz).format(TIME_FORMAT),
+                    place.label
+                ))
+                .await
+            }
+            None => self.unknown_place(ctx, arg).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for TimezonePlugin {
+    fn new_from_env() -> Result<Self> {
+        Ok(TimezonePlugin::new())
+    }
+
+    fn command_metadata(&self) -> Vec<CommandMetadata> {
+        vec![
+            CommandMetadata {
+                name: "time".to_string(),
+                short_help: "usage: time [nick|zone|city] | time <time> [place] in <place>. Shows or converts times between timezones.".to_string(),
+                full_help: "shows the time for you, for someone who has set their timezone with tz, or in a timezone or city like Europe/Berlin or Tokyo. nicks are checked before cities. \
+Given a time like 'time 3pm Chicago in Berlin', converts it from one place to another for today. Either place can be left out to use your own timezone.".to_string(),
+            },
+            CommandMetadata {
+                name: "tz".to_string(),
+                short_help: "usage: tz | tz set <zone> | tz unset. Shows or sets your timezone.".to_string(),
+                full_help: "shows your timezone, which other commands use to read and show times, like when reminders are due or which day a forecast is for. \
+set chooses a timezone by its name in the tz database or its city, like America/Chicago or Berlin, and unset goes back to the bot's default.".to_string(),
+            },
+        ]
+    }
+
+    async fn run(self, bot: Arc<Client>) -> Result<()> {
+        let mut stream = bot.subscribe();
+
+        while let Ok(ctx) = stream.recv().await {
+            let res = match ctx.as_event() {
+                Ok(Event::Command("time", arg)) => self.handle_time(&ctx, arg).await,
+                Ok(Event::Command("tz", arg)) => self.handle_tz(&ctx, arg).await,
+                _ => Ok(()),
+            };
+
+            crate::check_err(&ctx, res).await;
+        }
+
+        Err(format_err!("timezone plugin lagged"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_timezone() {
+        let cases = [
+            ("America/Chicago", Some(chrono_tz::America::Chicago)),
+            ("america/chicago", Some(chrono_tz::America::Chicago)),
+            (" Europe/Berlin ", Some(chrono_tz::Europe::Berlin)),
+            ("UTC", Some(Tz::UTC)),
+            ("Chicago", Some(chrono_tz::America::Chicago)),
+            ("new york", Some(chrono_tz::America::New_York)),
+            ("Europe/Chicago", None),
+            ("Mars/Olympus_Mons", None),
+            ("", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(find_timezone(input), expected, "Failed finding '{}'", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_conversion() {
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        let cases = [
+            (
+                "3pm Chicago in Berlin",
+                Some((time(15, 0), "Chicago", "Berlin")),
+            ),
+            (
+                "3 PM new york in Europe/London",
+                Some((time(15, 0), "new york", "Europe/London")),
+            ),
+            ("09:30 in bob", Some((time(9, 30), "", "bob"))),
+            ("noon Tokyo", Some((time(12, 0), "Tokyo", ""))),
+            ("11am", Some((time(11, 0), "", ""))),
+            ("Berlin", None),
+            ("3 Berlin", None),
+            ("", None),
+        ];
+
+        for (input, expected) in cases {
+            let expected =
+                expected.map(|(time, from, to)| (time, from.to_string(), to.to_string()));
+            assert_eq!(
+                parse_conversion(input),
+                expected,
+                "Failed parsing '{}'",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let chicago = chrono_tz::America::Chicago;
+        let berlin = chrono_tz::Europe::Berlin;
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+
+        // Late Friday in Chicago is already Saturday in UTC, but times are
+        // converted on the date where they're from.
+        let now = Utc.with_ymd_and_hms(2026, 10, 17, 3, 0, 0).unwrap();
+        let (at, converted) = convert(time(15, 0), chicago, berlin, &now).unwrap();
+        assert_eq!(at.to_string(), "2026-10-16 15:00:00 CDT");
+        assert_eq!(converted.to_string(), "2026-10-16 22:00:00 CEST");
+
+        // Europe changes its clocks a week before the US.
+        let now = Utc.with_ymd_and_hms(2026, 10, 27, 12, 0, 0).unwrap();
+        let (_, converted) = convert(time(15, 0), chicago, berlin, &now).unwrap();
+        assert_eq!(converted.to_string(), "2026-10-27 21:00:00 CET");
+
+        // Times skipped by daylight saving time don't exist.
+        let now = Utc.with_ymd_and_hms(2027, 3, 14, 12, 0, 0).unwrap();
+        assert!(convert(time(2, 30), chicago, berlin, &now).is_err());
+    }
+}
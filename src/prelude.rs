@@ -1,5 +1,4 @@
 pub use std::collections::HashMap;
-pub use std::pin::Pin;
 pub use std::sync::Arc;
 
 pub use anyhow::{format_err, Context as AnyhowContext};
@@ -8,7 +7,7 @@ pub use itertools::Itertools;
 pub use tokio_stream::{Stream, StreamExt};
 pub use unicode_segmentation::UnicodeSegmentation;
 
-pub use crate::client::{Client, ClientConfig, Context, Event};
+pub use crate::client::{Client, Context, Event};
 pub use crate::error::Result;
 pub use crate::plugin::{CommandMetadata, Plugin};
 pub use crate::proto;
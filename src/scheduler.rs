@@ -0,0 +1,424 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{mpsc, Notify};
+
+use crate::prelude::*;
+
+// How long a plugin has to complete a job before it is handed out again.
+const LEASE_SECS: i64 = 60;
+
+/// A unit of timed work owned by a single plugin.
+///
+/// Jobs are delivered at least once: a job stays in the database until the
+/// owning plugin calls `Scheduler::complete`, and will be delivered again if
+/// that doesn't happen within the lease or if the bot restarts.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub plugin: String,
+    pub key: String,
+    pub schedule: Option<String>,
+    pub run_at: i64,
+    payload: String,
+}
+
+impl Job {
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_str(&self.payload)
+            .with_context(|| format!("invalid payload for job {}", self.key))
+    }
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    db: sqlx::SqlitePool,
+    wakeup: Notify,
+    subscribers: Mutex<HashMap<String, mpsc::UnboundedSender<Job>>>,
+}
+
+pub fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Calculate the next time a cron expression fires after the given UNIX
+/// timestamp. Expressions use the 6 or 7 field format of the cron crate:
+/// "sec min hour day-of-month month day-of-week [year]".
+pub fn next_run(schedule: &str, after: i64) -> Result<i64> {
+    let schedule = cron::Schedule::from_str(schedule)
+        .map_err(|e| format_err!("invalid schedule \"{}\": {}", schedule, e))?;
+    let after = chrono::DateTime::from_timestamp(after, 0)
+        .ok_or_else(|| format_err!("invalid timestamp {}", after))?;
+
+    schedule
+        .after(&after)
+        .next()
+        .map(|next| next.timestamp())
+        .ok_or_else(|| format_err!("schedule never fires again"))
+}
+
+impl Scheduler {
+    pub fn new(db: sqlx::SqlitePool) -> Self {
+        Scheduler {
+            db,
+            wakeup: Notify::new(),
+            subscribers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register to receive all due jobs for the given plugin. Only one
+    /// receiver is kept per plugin; subscribing again replaces the old one.
+    pub fn subscribe(&self, plugin: &str) -> mpsc::UnboundedReceiver<Job> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.subscribers
+            .lock()
+            .unwrap()
+            .insert(plugin.to_string(), sender);
+        self.wakeup.notify_one();
+
+        receiver
+    }
+
+    /// Schedule a job to run once at the given UNIX timestamp. Keys are unique
+    /// per plugin, so scheduling an existing key replaces that job.
+    pub async fn schedule_once<T: Serialize>(
+        &self,
+        plugin: &str,
+        key: &str,
+        run_at: i64,
+        payload: &T,
+    ) -> Result<()> {
+        self.upsert(plugin, key, None, run_at, payload).await
+    }
+
+    /// Schedule a job which recurs according to a cron expression. See
+    /// `next_run` for the supported format.
+    pub async fn schedule_recurring<T: Serialize>(
+        &self,
+        plugin: &str,
+        key: &str,
+        schedule: &str,
+        payload: &T,
+    ) -> Result<()> {
+        let run_at = next_run(schedule, now())?;
+        self.upsert(plugin, key, Some(schedule), run_at, payload)
+            .await
+    }
+
+    async fn upsert<T: Serialize>(
+        &self,
+        plugin: &str,
+        key: &str,
+        schedule: Option<&str>,
+        run_at: i64,
+        payload: &T,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(payload)?;
+
+        sqlx::query!(
+            "INSERT INTO scheduled_jobs (plugin, key, payload, schedule, run_at) VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (plugin, key) DO UPDATE SET payload=EXCLUDED.payload, schedule=EXCLUDED.schedule, run_at=EXCLUDED.run_at, locked_until=NULL;",
+            plugin,
+            key,
+            payload,
+            schedule,
+            run_at,
+        )
+        .execute(&self.db)
+        .await?;
+
+        self.wakeup.notify_one();
+
+        Ok(())
+    }
+
+    /// Remove a job. Returns false if no job with that key existed.
+    pub async fn cancel(&self, plugin: &str, key: &str) -> Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM scheduled_jobs WHERE plugin=$1 AND key=$2;",
+            plugin,
+            key
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Mark a delivered job as handled. One-shot jobs are removed and
+    /// recurring jobs are moved to their next run time. If the job was
+    /// rescheduled while it was being handled, the new schedule is left
+    /// alone.
+    pub async fn complete(&self, job: &Job) -> Result<()> {
+        match &job.schedule {
+            Some(schedule) => {
+                let run_at = next_run(schedule, now())?;
+                sqlx::query!(
+                    "UPDATE scheduled_jobs SET run_at=$1, locked_until=NULL WHERE id=$2 AND run_at=$3;",
+                    run_at,
+                    job.id,
+                    job.run_at
+                )
+                .execute(&self.db)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    "DELETE FROM scheduled_jobs WHERE id=$1 AND run_at=$2;",
+                    job.id,
+                    job.run_at
+                )
+                .execute(&self.db)
+                .await?;
+            }
+        }
+
+        self.wakeup.notify_one();
+
+        Ok(())
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        // Nothing can be in flight when we start, so anything which was
+        // leased before a restart should be handed out again right away.
+        sqlx::query!("UPDATE scheduled_jobs SET locked_until=NULL;")
+            .execute(&self.db)
+            .await?;
+
+        loop {
+            let next_wakeup = self.dispatch_due().await?;
+
+            match next_wakeup {
+                Some(wake_at) => {
+                    let delay = Duration::from_secs((wake_at - now()).max(0) as u64);
+                    tokio::select! {
+                        _ = self.wakeup.notified() => {},
+                        _ = tokio::time::sleep(delay) => {},
+                    }
+                }
+                None => self.wakeup.notified().await,
+            }
+        }
+    }
+
+    /// Send every due job to its plugin and return when we next need to wake
+    /// up, if ever.
+    async fn dispatch_due(&self) -> Result<Option<i64>> {
+        let now = now();
+
+        let due = sqlx::query_as!(
+            Job,
+            r#"SELECT id as "id!", plugin, key, schedule, run_at, payload FROM scheduled_jobs
+WHERE run_at <= $1 AND (locked_until IS NULL OR locked_until <= $1)
+ORDER BY run_at ASC;"#,
+            now
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        for job in due {
+            let sender = self.subscribers.lock().unwrap().get(&job.plugin).cloned();
+
+            // If the plugin isn't running (or hasn't started yet), leave the
+            // job where it is for later.
+            let sender = match sender {
+                Some(sender) => sender,
+                None => continue,
+            };
+
+            let locked_until = now + LEASE_SECS;
+            sqlx::query!(
+                "UPDATE scheduled_jobs SET locked_until=$1 WHERE id=$2;",
+                locked_until,
+                job.id
+            )
+            .execute(&self.db)
+            .await?;
+
+            debug!(
+                "dispatching job {} to {} (due at {})",
+                job.key, job.plugin, job.run_at
+            );
+
+            if let Err(mpsc::error::SendError(job)) = sender.send(job) {
+                warn!("plugin {} stopped receiving jobs", job.plugin);
+                self.subscribers.lock().unwrap().remove(&job.plugin);
+                sqlx::query!(
+                    "UPDATE scheduled_jobs SET locked_until=NULL WHERE id=$1;",
+                    job.id
+                )
+                .execute(&self.db)
+                .await?;
+            }
+        }
+
+        let wakeups = sqlx::query!(
+            r#"SELECT plugin as "plugin!", MIN(MAX(run_at, COALESCE(locked_until, 0))) as "wake_at!: i64"
+FROM scheduled_jobs GROUP BY plugin;"#
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let subscribers = self.subscribers.lock().unwrap();
+
+        Ok(wakeups
+            .into_iter()
+            .filter(|row| subscribers.contains_key(&row.plugin))
+            .map(|row| row.wake_at)
+            .min())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn recv(jobs: &mut mpsc::UnboundedReceiver<Job>) -> Job {
+        tokio::time::timeout(Duration::from_secs(5), jobs.recv())
+            .await
+            .expect("timed out waiting for job")
+            .expect("scheduler stopped")
+    }
+
+    #[test]
+    fn test_next_run() -> Result<()> {
+        // 2024-01-01T00:00:00Z
+        let start = 1704067200;
+
+        assert_eq!(next_run("0 * * * * *", start)?, start + 60);
+        assert_eq!(next_run("0 30 9 * * *", start)?, start + 9 * 3600 + 1800);
+        // The first Monday in 2024 is the 1st, so the next one is a week out.
+        assert_eq!(next_run("0 0 0 * * Mon", start)?, start + 7 * 86400);
+
+        assert!(next_run("not a schedule", start).is_err());
+        assert!(next_run("0 0 0 1 1 * 2000", start).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_once() -> Result<()> {
+        let db = testing::memory_db().await?;
+        let scheduler = Arc::new(Scheduler::new(db.clone()));
+        let mut jobs = scheduler.subscribe("test");
+
+        scheduler
+            .schedule_once("test", "job", now() - 1, &42)
+            .await?;
+
+        let runner = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run().await }
+        });
+
+        let job = recv(&mut jobs).await;
+        assert_eq!(job.key, "job");
+        assert_eq!(job.payload::<i64>()?, 42);
+
+        scheduler.complete(&job).await?;
+
+        let remaining = sqlx::query!("SELECT COUNT(*) as count FROM scheduled_jobs;")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(remaining.count, 0);
+
+        runner.abort();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recurring_reschedules() -> Result<()> {
+        let db = testing::memory_db().await?;
+        let scheduler = Scheduler::new(db.clone());
+
+        scheduler
+            .schedule_recurring("test", "job", "0 0 * * * *", &())
+            .await?;
+
+        let job = sqlx::query_as!(
+            Job,
+            r#"SELECT id as "id!", plugin, key, schedule, run_at, payload FROM scheduled_jobs;"#
+        )
+        .fetch_one(&db)
+        .await?;
+        assert!(job.run_at > now());
+
+        scheduler.complete(&job).await?;
+
+        let row = sqlx::query!("SELECT run_at, locked_until FROM scheduled_jobs;")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(row.run_at % 3600, 0);
+        assert_eq!(row.locked_until, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_complete_after_reschedule() -> Result<()> {
+        let db = testing::memory_db().await?;
+        let scheduler = Scheduler::new(db.clone());
+
+        scheduler
+            .schedule_once("test", "job", now() - 120, &())
+            .await?;
+        let job = sqlx::query_as!(
+            Job,
+            r#"SELECT id as "id!", plugin, key, schedule, run_at, payload FROM scheduled_jobs;"#
+        )
+        .fetch_one(&db)
+        .await?;
+
+        // Rescheduling keeps the same row, so completing the job which was
+        // already handed out shouldn't remove the new schedule.
+        scheduler.schedule_once("test", "job", now(), &()).await?;
+        scheduler.complete(&job).await?;
+
+        let row = sqlx::query!("SELECT id, run_at FROM scheduled_jobs;")
+            .fetch_one(&db)
+            .await?;
+        assert_eq!(row.id, Some(job.id));
+        assert!(row.run_at > job.run_at);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_redelivered_after_restart() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let scheduler = Arc::new(Scheduler::new(db.clone()));
+        let mut jobs = scheduler.subscribe("test");
+        scheduler
+            .schedule_once("test", "job", now() - 1, &())
+            .await?;
+
+        let runner = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run().await }
+        });
+
+        // Receive the job, but never complete it.
+        recv(&mut jobs).await;
+        runner.abort();
+
+        let scheduler = Arc::new(Scheduler::new(db));
+        let mut jobs = scheduler.subscribe("test");
+        let runner = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run().await }
+        });
+
+        let job = recv(&mut jobs).await;
+        assert_eq!(job.key, "job");
+
+        runner.abort();
+
+        Ok(())
+    }
+}
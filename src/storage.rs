@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::prelude::*;
+use crate::scheduler::now;
+
+/// Simple key-value storage for plugins which don't need their own tables.
+///
+/// Values are stored as JSON and are namespaced by plugin. A Storage can be
+/// narrowed to a channel and/or user, and keys in one scope are never visible
+/// from another, including the unscoped one.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    db: sqlx::SqlitePool,
+    plugin: String,
+    channel_id: String,
+    user: String,
+}
+
+impl Storage {
+    pub fn new(db: sqlx::SqlitePool, plugin: &str) -> Self {
+        Storage {
+            db,
+            plugin: plugin.to_string(),
+            channel_id: String::new(),
+            user: String::new(),
+        }
+    }
+
+    pub fn channel(mut self, channel_id: &str) -> Self {
+        self.channel_id = channel_id.to_string();
+        self
+    }
+
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = user.to_string();
+        self
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let now = now();
+
+        let value = sqlx::query!(
+            "SELECT value FROM plugin_storage
+WHERE plugin=$1 AND channel_id=$2 AND user=$3 AND key=$4 AND (expires_at IS NULL OR expires_at > $5);",
+            self.plugin,
+            self.channel_id,
+            self.user,
+            key,
+            now,
+        )
+        .map(|row| row.value)
+        .fetch_optional(&self.db)
+        .await?;
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .with_context(|| format!("invalid stored value for {}", key))
+    }
+
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        self.upsert(key, value, None).await
+    }
+
+    /// Store a value which will disappear after the given duration.
+    pub async fn set_with_ttl<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.upsert(key, value, Some(now() + ttl.as_secs() as i64))
+            .await
+    }
+
+    async fn upsert<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        let value = serde_json::to_string(value)?;
+        let now = now();
+
+        // Expired values are never returned, but this keeps them from piling
+        // up forever.
+        sqlx::query!("DELETE FROM plugin_storage WHERE expires_at <= $1;", now)
+            .execute(&self.db)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO plugin_storage (plugin, channel_id, user, key, value, expires_at) VALUES ($1, $2, $3, $4, $5, $6)
+ON CONFLICT (plugin, channel_id, user, key) DO UPDATE SET value=EXCLUDED.value, expires_at=EXCLUDED.expires_at;",
+            self.plugin,
+            self.channel_id,
+            self.user,
+            key,
+            value,
+            expires_at,
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a key. Returns false if it didn't exist.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let now = now();
+
+        let result = sqlx::query!(
+            "DELETE FROM plugin_storage
+WHERE plugin=$1 AND channel_id=$2 AND user=$3 AND key=$4 AND (expires_at IS NULL OR expires_at > $5);",
+            self.plugin,
+            self.channel_id,
+            self.user,
+            key,
+            now,
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// List every key and value in this scope, ordered by key.
+    pub async fn list<T: DeserializeOwned>(&self) -> Result<Vec<(String, T)>> {
+        let now = now();
+
+        let rows = sqlx::query!(
+            "SELECT key, value FROM plugin_storage
+WHERE plugin=$1 AND channel_id=$2 AND user=$3 AND (expires_at IS NULL OR expires_at > $4)
+ORDER BY key;",
+            self.plugin,
+            self.channel_id,
+            self.user,
+            now,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let value = serde_json::from_str(&row.value)
+                    .with_context(|| format!("invalid stored value for {}", row.key))?;
+                Ok((row.key, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_get_set_delete() -> Result<()> {
+        let storage = Storage::new(testing::memory_db().await?, "test");
+
+        assert_eq!(storage.get::<String>("key").await?, None);
+
+        storage.set("key", &"value").await?;
+        assert_eq!(
+            storage.get::<String>("key").await?,
+            Some("value".to_string())
+        );
+
+        storage.set("key", &"other").await?;
+        assert_eq!(
+            storage.get::<String>("key").await?,
+            Some("other".to_string())
+        );
+
+        assert!(storage.delete("key").await?);
+        assert!(!storage.delete("key").await?);
+        assert_eq!(storage.get::<String>("key").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scopes() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let global = Storage::new(db.clone(), "test");
+        let channel = Storage::new(db.clone(), "test").channel("#chan");
+        let user = Storage::new(db.clone(), "test")
+            .channel("#chan")
+            .user("alice");
+        let other_plugin = Storage::new(db.clone(), "other");
+
+        global.set("key", &1).await?;
+        channel.set("key", &2).await?;
+        user.set("key", &3).await?;
+
+        assert_eq!(global.get::<i32>("key").await?, Some(1));
+        assert_eq!(channel.get::<i32>("key").await?, Some(2));
+        assert_eq!(user.get::<i32>("key").await?, Some(3));
+        assert_eq!(other_plugin.get::<i32>("key").await?, None);
+
+        channel.set("another", &4).await?;
+        assert_eq!(
+            channel.list::<i32>().await?,
+            vec![("another".to_string(), 4), ("key".to_string(), 2)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ttl() -> Result<()> {
+        let storage = Storage::new(testing::memory_db().await?, "test");
+
+        storage
+            .set_with_ttl("expired", &true, Duration::ZERO)
+            .await?;
+        storage
+            .set_with_ttl("fresh", &true, Duration::from_secs(3600))
+            .await?;
+
+        assert_eq!(storage.get::<bool>("expired").await?, None);
+        assert_eq!(storage.get::<bool>("fresh").await?, Some(true));
+        assert_eq!(storage.list::<bool>().await?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// Test helpers which stand in for a seabird core and remote HTTP APIs so
+// plugins can be exercised without a network connection.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use seabird::proto::seabird::seabird_server::{Seabird, SeabirdServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Request, Response, Status};
+
+use crate::client::ClientConfig;
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub struct FakeSeabird {
+    addr: SocketAddr,
+    latency: Duration,
+    sent: Mutex<Vec<proto::SendMessageRequest>>,
+}
+
+impl FakeSeabird {
+    /// Start a fake core on a random local port. Every request will wait for
+    /// the given latency before responding to simulate a remote server.
+    pub async fn spawn(latency: Duration) -> Result<Arc<Self>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+
+        let core = Arc::new(FakeSeabird {
+            addr: listener.local_addr()?,
+            latency,
+            sent: Mutex::new(Vec::new()),
+        });
+
+        let service = SeabirdServer::from_arc(core.clone());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        Ok(core)
+    }
+
+    /// Create a Client connected to this core, backed by an in-memory
+    /// database.
+    pub async fn client(&self) -> Result<Client> {
+        Client::new(ClientConfig::new(
+            format!("http://{}", self.addr),
+            "test-token".to_string(),
+            "sqlite::memory:".to_string(),
+            1,
+            BTreeSet::new(),
+            BTreeSet::new(),
+            BTreeSet::new(),
+        ))
+        .await
+    }
+
+    pub fn sent_messages(&self) -> Vec<proto::SendMessageRequest> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+/// A minimal HTTP server which answers each request with the next canned
+/// response and records the request line and headers it was sent.
+#[derive(Debug)]
+pub struct FakeHttp {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeHttp {
+    /// Start a server on a random local port. Once the canned responses run
+    /// out, every request gets a 404.
+    pub async fn spawn(responses: Vec<(u16, &str)>) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let mut responses: VecDeque<(u16, String)> = responses
+            .into_iter()
+            .map(|(status, body)| (status, body.to_string()))
+            .collect();
+
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Ok((mut conn, _)) = listener.accept().await {
+                    let mut head = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match conn.read(&mut buf).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => head.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8_lossy(&head).to_string());
+
+                    let (status, body) = responses.pop_front().unwrap_or((404, String::new()));
+                    let reason = reqwest::StatusCode::from_u16(status)
+                        .ok()
+                        .and_then(|status| status.canonical_reason())
+                        .unwrap_or("Unknown");
+                    let response = format!(
+                        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        reason,
+                        body.len(),
+                        body
+                    );
+                    let _ = conn.write_all(response.as_bytes()).await;
+                }
+            }
+        });
+
+        Ok(FakeHttp { addr, requests })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Create a migrated in-memory database. Every connection to an in-memory
+/// SQLite database gets its own copy, so the pool is limited to one.
+pub async fn memory_db() -> Result<sqlx::SqlitePool> {
+    let db = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await?;
+
+    crate::migrations::run(&db).await?;
+
+    Ok(db)
+}
+
+#[async_trait]
+impl Seabird for FakeSeabird {
+    type StreamEventsStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<proto::Event, Status>> + Send>>;
+
+    async fn stream_events(
+        &self,
+        _request: Request<proto::StreamEventsRequest>,
+    ) -> std::result::Result<Response<Self::StreamEventsStream>, Status> {
+        Ok(Response::new(Box::pin(futures::stream::pending())))
+    }
+
+    async fn perform_action(
+        &self,
+        _request: Request<proto::PerformActionRequest>,
+    ) -> std::result::Result<Response<proto::PerformActionResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        Ok(Response::new(proto::PerformActionResponse {}))
+    }
+
+    async fn perform_private_action(
+        &self,
+        _request: Request<proto::PerformPrivateActionRequest>,
+    ) -> std::result::Result<Response<proto::PerformPrivateActionResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        Ok(Response::new(proto::PerformPrivateActionResponse {}))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<proto::SendMessageRequest>,
+    ) -> std::result::Result<Response<proto::SendMessageResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        self.sent.lock().unwrap().push(request.into_inner());
+        Ok(Response::new(proto::SendMessageResponse {}))
+    }
+
+    async fn send_private_message(
+        &self,
+        _request: Request<proto::SendPrivateMessageRequest>,
+    ) -> std::result::Result<Response<proto::SendPrivateMessageResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        Ok(Response::new(proto::SendPrivateMessageResponse {}))
+    }
+
+    async fn join_channel(
+        &self,
+        _request: Request<proto::JoinChannelRequest>,
+    ) -> std::result::Result<Response<proto::JoinChannelResponse>, Status> {
+        Err(Status::unimplemented("join_channel"))
+    }
+
+    async fn leave_channel(
+        &self,
+        _request: Request<proto::LeaveChannelRequest>,
+    ) -> std::result::Result<Response<proto::LeaveChannelResponse>, Status> {
+        Err(Status::unimplemented("leave_channel"))
+    }
+
+    async fn update_channel_info(
+        &self,
+        _request: Request<proto::UpdateChannelInfoRequest>,
+    ) -> std::result::Result<Response<proto::UpdateChannelInfoResponse>, Status> {
+        Err(Status::unimplemented("update_channel_info"))
+    }
+
+    async fn list_backends(
+        &self,
+        _request: Request<proto::ListBackendsRequest>,
+    ) -> std::result::Result<Response<proto::ListBackendsResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        Ok(Response::new(proto::ListBackendsResponse::default()))
+    }
+
+    async fn get_backend_info(
+        &self,
+        _request: Request<proto::BackendInfoRequest>,
+    ) -> std::result::Result<Response<proto::BackendInfoResponse>, Status> {
+        Err(Status::unimplemented("get_backend_info"))
+    }
+
+    async fn list_channels(
+        &self,
+        _request: Request<proto::ListChannelsRequest>,
+    ) -> std::result::Result<Response<proto::ListChannelsResponse>, Status> {
+        Err(Status::unimplemented("list_channels"))
+    }
+
+    async fn get_channel_info(
+        &self,
+        _request: Request<proto::ChannelInfoRequest>,
+    ) -> std::result::Result<Response<proto::ChannelInfoResponse>, Status> {
+        Err(Status::unimplemented("get_channel_info"))
+    }
+
+    async fn get_core_info(
+        &self,
+        _request: Request<proto::CoreInfoRequest>,
+    ) -> std::result::Result<Response<proto::CoreInfoResponse>, Status> {
+        Err(Status::unimplemented("get_core_info"))
+    }
+
+    async fn registered_commands(
+        &self,
+        _request: Request<proto::CommandsRequest>,
+    ) -> std::result::Result<Response<proto::CommandsResponse>, Status> {
+        tokio::time::sleep(self.latency).await;
+        Ok(Response::new(proto::CommandsResponse::default()))
+    }
+}
@@ -0,0 +1,213 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use lru::LruCache;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::prelude::*;
+use crate::scheduler::now;
+use crate::storage::Storage;
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Vec<Arc<CacheStats>>> = Mutex::new(Vec::new());
+}
+
+/// Hit and miss counters for a single cache.
+#[derive(Debug)]
+pub struct CacheStats {
+    pub source: &'static str,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters for every cache which has been created, in creation order.
+pub fn stats() -> Vec<Arc<CacheStats>> {
+    REGISTRY.lock().unwrap().clone()
+}
+
+/// Whether caches should also keep their entries in the database so they
+/// survive restarts. Controlled by $SEABIRD_CACHE_PERSIST.
+pub fn persistence_enabled() -> Result<bool> {
+    utils::env_var_or("SEABIRD_CACHE_PERSIST", false)
+}
+
+/// An in-memory LRU cache of upstream API responses where every entry
+/// expires after a fixed TTL, optionally backed by plugin storage.
+pub struct Cache<V> {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, (i64, V)>>,
+    storage: Mutex<Option<Storage>>,
+    stats: Arc<CacheStats>,
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> Cache<V> {
+    pub fn new(source: &'static str, capacity: usize, ttl: Duration) -> Self {
+        let stats = Arc::new(CacheStats {
+            source,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        });
+        REGISTRY.lock().unwrap().push(stats.clone());
+
+        Cache {
+            ttl,
+            entries: Mutex::new(LruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+            storage: Mutex::new(None),
+            stats,
+        }
+    }
+
+    /// Also store entries in the database so they survive restarts.
+    pub fn persist(&self, db: sqlx::SqlitePool) {
+        *self.storage.lock().unwrap() = Some(Storage::new(db, "cache"));
+    }
+
+    fn storage_key(&self, key: &str) -> String {
+        format!("{}:{}", self.stats.source, key)
+    }
+
+    async fn get(&self, key: &str) -> Option<V> {
+        let now = now();
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(key) {
+                Some((expires_at, value)) if *expires_at > now => return Some(value.clone()),
+                Some(_) => {
+                    entries.pop(key);
+                }
+                None => {}
+            }
+        }
+
+        let storage = self.storage.lock().unwrap().clone()?;
+
+        // Persistence is best-effort; failing to read from the database just
+        // means we need to go upstream.
+        match storage.get::<(i64, V)>(&self.storage_key(key)).await {
+            Ok(Some((expires_at, value))) if expires_at > now => {
+                self.entries
+                    .lock()
+                    .unwrap()
+                    .put(key.to_string(), (expires_at, value.clone()));
+                Some(value)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                warn!("failed to read {} from cache: {}", key, err);
+                None
+            }
+        }
+    }
+
+    async fn insert(&self, key: &str, value: V) {
+        let expires_at = now() + self.ttl.as_secs() as i64;
+
+        let storage = self.storage.lock().unwrap().clone();
+        if let Some(storage) = storage {
+            if let Err(err) = storage
+                .set_with_ttl(&self.storage_key(key), &(expires_at, &value), self.ttl)
+                .await
+            {
+                warn!("failed to write {} to cache: {}", key, err);
+            }
+        }
+
+        self.entries
+            .lock()
+            .unwrap()
+            .put(key.to_string(), (expires_at, value));
+    }
+
+    /// Return the cached value for this key, or call fetch to look it up and
+    /// cache the result. Errors are returned as-is and never cached.
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>>,
+    {
+        if let Some(value) = self.get(key).await {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+
+        let value = fetch().await?;
+        self.insert(key, value.clone()).await;
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn test_get_or_fetch() -> Result<()> {
+        let cache = Cache::new("test", 2, Duration::from_secs(3600));
+
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(1) }).await?, 1);
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(2) }).await?, 1);
+        assert_eq!((cache.stats.hits(), cache.stats.misses()), (1, 1));
+
+        // Errors aren't cached.
+        assert!(cache
+            .get_or_fetch("b", || async { Err(format_err!("upstream failed")) })
+            .await
+            .is_err());
+        assert_eq!(cache.get_or_fetch("b", || async { Ok(3) }).await?, 3);
+
+        // "a" is the least recently used entry, so it gets evicted.
+        cache.get_or_fetch("c", || async { Ok(4) }).await?;
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(5) }).await?, 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expiry() -> Result<()> {
+        let cache = Cache::new("test", 10, Duration::ZERO);
+
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(1) }).await?, 1);
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(2) }).await?, 2);
+        assert_eq!(cache.stats.hits(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_persistence() -> Result<()> {
+        let db = testing::memory_db().await?;
+
+        let cache = Cache::new("test", 10, Duration::from_secs(3600));
+        cache.persist(db.clone());
+        cache.get_or_fetch("a", || async { Ok(1) }).await?;
+
+        // A fresh cache, as if the bot had restarted, should find the entry.
+        let cache = Cache::<i32>::new("test", 10, Duration::from_secs(3600));
+        cache.persist(db);
+        assert_eq!(cache.get_or_fetch("a", || async { Ok(2) }).await?, 1);
+        assert_eq!(cache.stats.hits(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::prelude::*;
+
+const USER_AGENT: &str = concat!(
+    "seabird-plugin-bundle/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/seabird-chat/seabird-plugin-bundle)"
+);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+// How many requests may be in flight to a single host at once.
+const HOST_LIMIT: usize = 4;
+
+lazy_static! {
+    static ref SHARED: Client = Client::new().expect("failed to build shared HTTP client");
+}
+
+/// Get a handle to the HTTP client shared by all plugins. Handles are cheap
+/// to clone and share their connection pool and per-host limits.
+pub fn client() -> Client {
+    SHARED.clone()
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: reqwest::Client,
+    max_retries: u32,
+    retry_delay: Duration,
+    host_semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl Client {
+    pub fn new() -> Result<Self> {
+        let inner = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Client {
+            inner,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            host_semaphores: Default::default(),
+        })
+    }
+
+    /// Override how many times a failed GET is retried and the base delay
+    /// between attempts, which doubles after each failure.
+    #[cfg(test)]
+    pub fn with_retries(mut self, max_retries: u32, retry_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    fn host_semaphore(&self, host: &str) -> Arc<Semaphore> {
+        self.host_semaphores
+            .lock()
+            .unwrap()
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(HOST_LIMIT)))
+            .clone()
+    }
+
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        let base = self.retry_delay * 2u32.pow(attempt);
+        let jitter = rand::thread_rng().gen_range(0..=self.retry_delay.as_millis() as u64);
+        base + Duration::from_millis(jitter)
+    }
+
+    /// Send a GET request. Because GETs are idempotent, connection failures,
+    /// timeouts, 429s and 5xx responses are retried with backoff. Any other
+    /// response is returned as-is, so callers still need to check the status.
+    pub async fn get<Q: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        query: &Q,
+    ) -> Result<reqwest::Response> {
+        let request = self.inner.get(url).query(query).build()?;
+        let semaphore = self.host_semaphore(request.url().host_str().unwrap_or_default());
+
+        let mut attempt = 0;
+        loop {
+            let start = std::time::Instant::now();
+
+            let result = {
+                let _permit = semaphore.acquire().await?;
+                self.inner
+                    .execute(
+                        request
+                            .try_clone()
+                            .ok_or_else(|| format_err!("failed to clone GET request"))?,
+                    )
+                    .await
+            };
+
+            let retry_reason = match &result {
+                Ok(resp)
+                    if resp.status().is_server_error()
+                        || resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    Some(resp.status().to_string())
+                }
+                Err(err) if err.is_connect() || err.is_timeout() => Some(err.to_string()),
+                _ => None,
+            };
+
+            match retry_reason {
+                Some(reason) if attempt < self.max_retries => {
+                    let delay = self.retry_delay(attempt);
+                    warn!(
+                        "GET {} failed ({}), retrying in {}ms",
+                        request.url(),
+                        reason,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => {
+                    debug!(
+                        "GET {} finished in {}ms",
+                        request.url(),
+                        start.elapsed().as_millis()
+                    );
+                    return Ok(result?);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttp;
+
+    fn test_client() -> Result<Client> {
+        Ok(Client::new()?.with_retries(2, Duration::from_millis(1)))
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(200, "ok")]).await?;
+
+        let resp = test_client()?
+            .get(&server.url("/path"), &[("q", "a b")])
+            .await?;
+        assert_eq!(resp.text().await?, "ok");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("GET /path?q=a+b HTTP/1.1"));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("user-agent: seabird-plugin-bundle/"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(503, ""), (429, ""), (200, "ok")]).await?;
+
+        let resp = test_client()?.get(&server.url("/"), &()).await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(server.requests().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_bounded() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(500, ""), (500, ""), (500, ""), (200, "")]).await?;
+
+        let resp = test_client()?.get(&server.url("/"), &()).await?;
+        assert_eq!(resp.status(), 500);
+        assert_eq!(server.requests().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_not_retried() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(404, ""), (200, "")]).await?;
+
+        let resp = test_client()?.get(&server.url("/"), &()).await?;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(server.requests().len(), 1);
+
+        Ok(())
+    }
+}
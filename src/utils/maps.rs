@@ -1,30 +1,53 @@
-use serde::Deserialize;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
+use crate::utils::cache::Cache;
 
 const BASE_URL: &str = "https://maps.googleapis.com/maps/api";
 
+// Places don't move, so geocoding results can be kept for a long time.
+const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const CACHE_SIZE: usize = 256;
+
 pub struct Client {
     api_key: String,
-    inner: reqwest::Client,
+    base_url: String,
+    inner: utils::http::Client,
+    cache: Cache<Vec<Location>>,
 }
 
 impl Client {
     pub fn new(api_key: String) -> Self {
+        Self::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
         Client {
             api_key,
-            inner: reqwest::Client::new(),
+            base_url,
+            inner: utils::http::client(),
+            cache: Cache::new("geocode", CACHE_SIZE, CACHE_TTL),
         }
     }
 
+    pub fn persist_cache(&self, db: sqlx::SqlitePool) {
+        self.cache.persist(db);
+    }
+
     pub async fn forward(&self, loc: &str) -> Result<Vec<Location>> {
-        let url = format!("{}/geocode/json", BASE_URL);
+        self.cache
+            .get_or_fetch(&loc.trim().to_lowercase(), || self.forward_uncached(loc))
+            .await
+    }
+
+    async fn forward_uncached(&self, loc: &str) -> Result<Vec<Location>> {
+        let url = format!("{}/geocode/json", self.base_url);
 
         let response: GeocodeResponse = self
             .inner
-            .get(&url)
-            .query(&[("key", &self.api_key[..]), ("address", loc)])
-            .send()
+            .get(&url, &[("key", &self.api_key[..]), ("address", loc)])
             .await?
             .error_for_status()?
             .json()
@@ -45,7 +68,7 @@ impl Client {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Location {
     pub display_name: String,
     pub lat: f64,
@@ -74,3 +97,52 @@ struct GeometryLocation {
     lat: f64,
     lng: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttp;
+
+    #[tokio::test]
+    async fn test_forward() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(
+            200,
+            r#"{
+                "status": "OK",
+                "results": [{
+                    "formatted_address": "Chicago, IL, USA",
+                    "geometry": {"location": {"lat": 41.8781136, "lng": -87.6297982}}
+                }]
+            }"#,
+        )])
+        .await?;
+
+        let client = Client::with_base_url("key".to_string(), server.url(""));
+        let locations = client.forward("chicago").await?;
+
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].display_name, "Chicago, IL, USA");
+        assert_eq!(locations[0].lat, 41.8781136);
+        assert!(server.requests()[0].starts_with("GET /geocode/json?key=key&address=chicago "));
+
+        // The second lookup should be served from the cache.
+        client.forward("Chicago").await?;
+        assert_eq!(server.requests().len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forward_error_status() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(
+            200,
+            r#"{"status": "REQUEST_DENIED", "results": []}"#,
+        )])
+        .await?;
+
+        let client = Client::with_base_url("key".to_string(), server.url(""));
+        assert!(client.forward("chicago").await.is_err());
+
+        Ok(())
+    }
+}
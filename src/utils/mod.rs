@@ -1,5 +1,9 @@
 use crate::prelude::*;
 
+pub mod cache;
+pub mod http;
+pub mod when;
+
 // API clients
 pub mod maps;
 pub mod openweathermap;
@@ -21,6 +25,21 @@ pub fn to_sentence_case(s: &str) -> String {
     cap
 }
 
+/// Read and parse an optional setting from the environment, falling back to
+/// the default if it isn't set.
+pub fn env_var_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match dotenvy::var(name) {
+        Ok(val) => val
+            .parse()
+            .map_err(|e| format_err!("Invalid ${}: {}", name, e)),
+        Err(_) => Ok(default),
+    }
+}
+
 pub fn clamp<T: PartialOrd>(input: T, min: T, max: T) -> T {
     debug_assert!(min <= max, "min must be less than or equal to max");
     if input < min {
@@ -1,68 +1,75 @@
-use serde::Deserialize;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 
 use crate::prelude::*;
+use crate::utils::cache::Cache;
 
 const BASE_URL: &str = "https://api.openweathermap.org/data/3.0";
 
+// Current conditions and forecasts both come from the same call, so they
+// share a cache.
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+const CACHE_SIZE: usize = 128;
+
 pub struct Client {
     api_key: String,
-    inner: reqwest::Client,
+    base_url: String,
+    inner: utils::http::Client,
+    cache: Cache<OneCallResponse>,
 }
 
 impl Client {
     pub fn new(api_key: String) -> Self {
+        Self::with_base_url(api_key, BASE_URL.to_string())
+    }
+
+    pub fn with_base_url(api_key: String, base_url: String) -> Self {
         Client {
             api_key,
-            inner: reqwest::Client::new(),
+            base_url,
+            inner: utils::http::client(),
+            cache: Cache::new("weather", CACHE_SIZE, CACHE_TTL),
         }
     }
 
+    pub fn persist_cache(&self, db: sqlx::SqlitePool) {
+        self.cache.persist(db);
+    }
+
     pub async fn forecast(&self, lat: f64, lng: f64) -> Result<Forecast> {
-        let url = format!("{}/onecall", BASE_URL);
-
-        let query = vec![
-            ("appid", self.api_key.clone()),
-            ("lat", format!("{:.16}", lat)),
-            ("lon", format!("{:.16}", lng)),
-            ("units", "imperial".to_string()),
-            ("exclude", "minutely,hourly,alerts".to_string()),
-        ];
-
-        let res: OneCallResponse = self
-            .inner
-            .get(&url)
-            .query(&query)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-
-        res.try_into()
+        self.one_call(lat, lng).await?.try_into()
     }
 
     pub async fn weather(&self, lat: f64, lng: f64) -> Result<CurrentWeather> {
-        let url = format!("{}/onecall", BASE_URL);
-
-        let query = vec![
-            ("appid", self.api_key.clone()),
-            ("lat", format!("{:.16}", lat)),
-            ("lon", format!("{:.16}", lng)),
-            ("units", "imperial".to_string()),
-            ("exclude", "minutely,hourly,alerts".to_string()),
-        ];
-
-        let res: OneCallResponse = self
-            .inner
-            .get(&url)
-            .query(&query)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
-
-        res.try_into()
+        self.one_call(lat, lng).await?.try_into()
+    }
+
+    async fn one_call(&self, lat: f64, lng: f64) -> Result<OneCallResponse> {
+        // About 10m of precision is plenty to tell locations apart.
+        let key = format!("{:.4},{:.4}", lat, lng);
+
+        self.cache
+            .get_or_fetch(&key, || async {
+                let url = format!("{}/onecall", self.base_url);
+
+                let query = vec![
+                    ("appid", self.api_key.clone()),
+                    ("lat", format!("{:.16}", lat)),
+                    ("lon", format!("{:.16}", lng)),
+                    ("units", "imperial".to_string()),
+                    ("exclude", "minutely,hourly,alerts".to_string()),
+                ];
+
+                Ok(self
+                    .inner
+                    .get(&url, &query)
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?)
+            })
+            .await
     }
 }
 
@@ -128,6 +135,7 @@ pub struct Weather {
 pub type Forecast = Vec<Weather>;
 
 pub struct CurrentWeather {
+    #[allow(dead_code)]
     pub time: time::OffsetDateTime,
     pub temperature: f64,
     pub temperature_feels_like: f64,
@@ -137,13 +145,13 @@ pub struct CurrentWeather {
     pub summary: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct OneCallResponse {
     current: CurrentResponse,
     daily: Vec<DailyResponse>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct CurrentResponse {
     dt: i64,
     temp: f64,
@@ -152,7 +160,7 @@ struct CurrentResponse {
     weather: Vec<WeatherStatus>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct DailyResponse {
     dt: i64,
     temp: DailyTemperatureResponse,
@@ -160,13 +168,58 @@ struct DailyResponse {
     humidity: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct DailyTemperatureResponse {
     min: f64,
     max: f64,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct WeatherStatus {
     description: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttp;
+
+    #[tokio::test]
+    async fn test_weather() -> Result<()> {
+        let server = FakeHttp::spawn(vec![(
+            200,
+            r#"{
+                "current": {
+                    "dt": 1704067200,
+                    "temp": 30.5,
+                    "feels_like": 22.1,
+                    "humidity": 80,
+                    "weather": [{"description": "light snow"}, {"description": "mist"}]
+                },
+                "daily": [{
+                    "dt": 1704067200,
+                    "temp": {"min": 20.0, "max": 33.0},
+                    "humidity": 75,
+                    "weather": []
+                }]
+            }"#,
+        )])
+        .await?;
+
+        let client = Client::with_base_url("key".to_string(), server.url(""));
+        let weather = client.weather(41.0, -87.0).await?;
+
+        assert_eq!(weather.temperature, 30.5);
+        assert_eq!(weather.temperature_high, 33.0);
+        assert_eq!(weather.summary, "light snow, mist");
+        assert!(server.requests()[0].starts_with("GET /onecall?appid=key&"));
+
+        // The forecast uses the same call, so it should come from the cache.
+        let forecast = client.forecast(41.0, -87.0).await?;
+        assert_eq!(forecast.len(), 1);
+        assert_eq!(forecast[0].summary, "unknown");
+        assert_eq!(server.requests().len(), 1);
+
+        Ok(())
+    }
+}
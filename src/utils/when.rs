@@ -0,0 +1,1143 @@
+//! Parsing the times people type in chat, like "5m", "1h30m",
+//! "tomorrow at 9am", "friday 14:00" or "every weekday at 9:55", and
+//! formatting them back.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{
+    DateTime, Datelike, Days, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone,
+    Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::prelude::*;
+
+// Times without a clock time, like "tomorrow", are at 9am.
+const DEFAULT_HOUR: u32 = 9;
+
+// The longest time expression we look for at the start of a message.
+const MAX_WORDS: usize = 8;
+
+// Recurring reminders can't repeat more often than this.
+const MIN_INTERVAL_SECS: u64 = 60;
+
+/// A time parsed from a message.
+#[derive(Debug, PartialEq)]
+pub struct When {
+    pub at: DateTime<Tz>,
+
+    // Whether the time was given relative to now, like "5m", rather than as
+    // a clock time or date.
+    pub relative: bool,
+}
+
+/// Format a number of seconds roughly, like "5 minutes" or "2 days".
+pub fn format_duration(secs: i64) -> String {
+    let secs = secs.unsigned_abs();
+    if secs < 60 {
+        format!("{} second{}", secs, if secs == 1 { "" } else { "s" })
+    } else if secs < 3600 {
+        let mins = secs / 60;
+        format!("{} minute{}", mins, if mins == 1 { "" } else { "s" })
+    } else if secs < 86400 {
+        let hours = secs / 3600;
+        format!("{} hour{}", hours, if hours == 1 { "" } else { "s" })
+    } else {
+        let days = secs / 86400;
+        format!("{} day{}", days, if days == 1 { "" } else { "s" })
+    }
+}
+
+fn unit_seconds(unit: &str) -> Option<u64> {
+    Some(match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 60 * 60 * 24,
+        "w" | "wk" | "wks" | "week" | "weeks" => 60 * 60 * 24 * 7,
+        _ => return None,
+    })
+}
+
+/// Parse a single token like "5m" or "1h30m" into seconds.
+fn parse_compact_duration(token: &str) -> Result<u64> {
+    let mut total: u64 = 0;
+    let mut rest = token;
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (num_str, after) = rest.split_at(digits);
+        let letters = after
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(letters);
+
+        let num: u64 = num_str
+            .parse()
+            .map_err(|_| format_err!("Invalid number in duration"))?;
+        if unit.is_empty() {
+            return Err(format_err!("Invalid duration format"));
+        }
+        let unit = unit_seconds(unit)
+            .ok_or_else(|| format_err!("Unknown duration unit '{}'. Use s/m/h/d/w", unit))?;
+
+        total = num
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| format_err!("Duration is too long"))?;
+        rest = after;
+    }
+
+    Ok(total)
+}
+
+/// Parse a duration like "5m", "1h30m", "1h 30m" or "2 hours".
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim().to_lowercase();
+    let mut tokens = s.split_whitespace().peekable();
+
+    if tokens.peek().is_none() {
+        return Err(format_err!("Invalid duration format"));
+    }
+
+    let mut total: u64 = 0;
+    while let Some(token) = tokens.next() {
+        // Allow a bare number followed by a unit word, like "2 hours".
+        let secs = match (
+            token.parse::<u64>(),
+            tokens.peek().copied().and_then(unit_seconds),
+        ) {
+            (Ok(num), Some(unit)) => {
+                tokens.next();
+                num.checked_mul(unit)
+                    .ok_or_else(|| format_err!("Duration is too long"))?
+            }
+            _ => parse_compact_duration(token)?,
+        };
+
+        total = total
+            .checked_add(secs)
+            .ok_or_else(|| format_err!("Duration is too long"))?;
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "weds" | "wednesday" => Weekday::Wed,
+        "thu" | "thur" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn parse_month(s: &str) -> Option<u32> {
+    Some(match s {
+        "jan" | "january" => 1,
+        "feb" | "february" => 2,
+        "mar" | "march" => 3,
+        "apr" | "april" => 4,
+        "may" => 5,
+        "jun" | "june" => 6,
+        "jul" | "july" => 7,
+        "aug" | "august" => 8,
+        "sep" | "sept" | "september" => 9,
+        "oct" | "october" => 10,
+        "nov" | "november" => 11,
+        "dec" | "december" => 12,
+        _ => return None,
+    })
+}
+
+/// Parse a day of the month like "5" or "5th".
+fn parse_day_of_month(s: &str) -> Option<u32> {
+    let s = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| s.strip_suffix(suffix))
+        .unwrap_or(s);
+
+    s.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+/// Parse a clock time like "9am", "9:30pm", "14:00", "noon" or "midnight".
+/// Bare hours like "9" are only accepted with am/pm, which can be a
+/// separate word.
+pub fn parse_clock(s: &str, meridiem: Option<&str>) -> Option<NaiveTime> {
+    match s {
+        "noon" if meridiem.is_none() => return NaiveTime::from_hms_opt(12, 0, 0),
+        "midnight" if meridiem.is_none() => return NaiveTime::from_hms_opt(0, 0, 0),
+        _ => {}
+    }
+
+    let (s, meridiem) = match meridiem {
+        Some(meridiem) => (s, Some(meridiem)),
+        None => match s.strip_suffix("am").or_else(|| s.strip_suffix("a")) {
+            Some(s) => (s, Some("am")),
+            None => match s.strip_suffix("pm").or_else(|| s.strip_suffix("p")) {
+                Some(s) => (s, Some("pm")),
+                None => (s, None),
+            },
+        },
+    };
+
+    let (hour, minute) = match s.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour.parse().ok()?, minute.parse().ok()?),
+        Some(_) => return None,
+        None if meridiem.is_some() => (s.parse().ok()?, 0),
+        None => return None,
+    };
+
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some("am") => hour % 12,
+        Some("pm") => hour % 12 + 12,
+        Some(_) => return None,
+        None => hour,
+    };
+
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[derive(Debug, PartialEq)]
+enum Day {
+    Today,
+    Tomorrow,
+    Weekday { weekday: Weekday, next: bool },
+    Date(NaiveDate),
+    Yearly { month: u32, day: u32 },
+}
+
+/// Parse a clock time, a day, or both, like "at 17:30", "tomorrow at 9am",
+/// "friday 14:00", "next tue", "dec 25 noon" or "2026-12-25 8pm".
+fn parse_absolute(s: &str, now: &DateTime<Tz>) -> Result<DateTime<Tz>> {
+    let tokens: Vec<&str> = s.split_whitespace().collect();
+
+    let mut day = None;
+    let mut clock = None;
+
+    let mut set_day = |new_day| {
+        if day.replace(new_day).is_some() {
+            return Err(format_err!("More than one day given"));
+        }
+        Ok(())
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        let next = tokens.get(i + 1).copied();
+        i += 1;
+
+        if token == "at" || token == "on" {
+            continue;
+        }
+
+        if clock.is_none() {
+            // Check for a separate am/pm word first so "9 am" works.
+            if let Some(time) = next
+                .filter(|next| matches!(*next, "am" | "pm"))
+                .and_then(|meridiem| parse_clock(token, Some(meridiem)))
+            {
+                clock = Some(time);
+                i += 1;
+                continue;
+            }
+
+            if let Some(time) = parse_clock(token, None) {
+                clock = Some(time);
+                continue;
+            }
+        }
+
+        match token {
+            "today" => set_day(Day::Today)?,
+            "tomorrow" | "tmrw" => set_day(Day::Tomorrow)?,
+            "next" => match next.and_then(parse_weekday) {
+                Some(weekday) => {
+                    set_day(Day::Weekday {
+                        weekday,
+                        next: true,
+                    })?;
+                    i += 1;
+                }
+                None => return Err(format_err!("Expected a weekday after \"next\"")),
+            },
+            _ => {
+                if let Some(weekday) = parse_weekday(token) {
+                    set_day(Day::Weekday {
+                        weekday,
+                        next: false,
+                    })?;
+                } else if let Ok(date) = NaiveDate::parse_from_str(token, "%Y-%m-%d") {
+                    set_day(Day::Date(date))?;
+                } else if let Some(month) = parse_month(token) {
+                    // "dec 25"
+                    let day = next
+                        .and_then(parse_day_of_month)
+                        .ok_or_else(|| format_err!("Expected a day after \"{}\"", token))?;
+                    set_day(Day::Yearly { month, day })?;
+                    i += 1;
+                } else if let (Some(day), Some(month)) =
+                    (parse_day_of_month(token), next.and_then(parse_month))
+                {
+                    // "25 dec"
+                    set_day(Day::Yearly { month, day })?;
+                    i += 1;
+                } else {
+                    return Err(format_err!("Couldn't understand \"{}\"", token));
+                }
+            }
+        }
+    }
+
+    if day.is_none() && clock.is_none() {
+        return Err(format_err!("No time given"));
+    }
+
+    let today = now.date_naive();
+    let time = clock.unwrap_or_else(|| NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0).unwrap());
+    let localize = |date: NaiveDate| -> Result<DateTime<Tz>> {
+        now.timezone()
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+            .ok_or_else(|| {
+                format_err!(
+                    "{} {} doesn't exist in {}",
+                    date,
+                    time.format("%H:%M"),
+                    now.timezone()
+                )
+            })
+    };
+
+    let at = match day {
+        // A bare clock time is the next time it comes around.
+        None => {
+            let at = localize(today)?;
+            if at <= *now {
+                localize(today + Days::new(1))?
+            } else {
+                at
+            }
+        }
+        Some(Day::Today) => localize(today)?,
+        Some(Day::Tomorrow) => localize(today + Days::new(1))?,
+        Some(Day::Weekday { weekday, next }) => {
+            let days_ahead =
+                (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+            let at = localize(today + Days::new(days_ahead.into()))?;
+            if (next && days_ahead == 0) || at <= *now {
+                localize(today + Days::new(u64::from(days_ahead) + 7))?
+            } else {
+                at
+            }
+        }
+        Some(Day::Date(date)) => localize(date)?,
+        Some(Day::Yearly { month, day }) => {
+            // The next time the date comes around, which for Feb 29 may be
+            // several years away. Leap years are never more than 8 apart.
+            let mut found = None;
+            for date in (today.year()..=today.year() + 8)
+                .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+            {
+                let at = localize(date)?;
+                if at > *now {
+                    found = Some(at);
+                    break;
+                }
+            }
+            found.ok_or_else(|| format_err!("Invalid date"))?
+        }
+    };
+
+    if at <= *now {
+        return Err(format_err!("That time has already passed"));
+    }
+
+    Ok(at)
+}
+
+fn looks_like_duration(s: &str) -> bool {
+    let token = s.split_whitespace().next().unwrap_or_default();
+    let unit = token.trim_start_matches(|c: char| c.is_ascii_digit());
+
+    unit.len() < token.len()
+        && unit.chars().all(|c| c.is_ascii_alphabetic())
+        && !matches!(unit, "am" | "a" | "pm" | "p" | "st" | "nd" | "rd" | "th")
+}
+
+/// Parse a time expression, either relative like "5m" or "in 2 hours", or
+/// absolute like "tomorrow at 9am". Absolute times are in now's timezone.
+pub fn parse_when(s: &str, now: &DateTime<Tz>) -> Result<When> {
+    let s = s.trim().to_lowercase();
+    let s = s.strip_prefix("in ").unwrap_or(&s);
+
+    match parse_duration(s) {
+        Ok(duration) => Ok(When {
+            at: *now + chrono::Duration::from_std(duration)?,
+            relative: true,
+        }),
+        Err(duration_err) => match parse_absolute(s, now) {
+            Ok(at) => Ok(When {
+                at,
+                relative: false,
+            }),
+            // Something like "5x" was probably meant to be a duration, so
+            // that's the more helpful error.
+            Err(_) if looks_like_duration(s) => Err(duration_err),
+            Err(err) => Err(err),
+        },
+    }
+}
+
+/// Parse the longest prefix of s which parse accepts, returning the result
+/// and the rest of s. If nothing matches, the error from parsing the first
+/// word is returned.
+fn parse_prefix<T>(s: &str, parse: impl Fn(&str) -> Result<T>) -> Result<(T, &str)> {
+    let mut ends = Vec::new();
+    let mut in_word = false;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() && in_word {
+            ends.push(i);
+        }
+        in_word = !c.is_whitespace();
+    }
+    if in_word {
+        ends.push(s.len());
+    }
+    ends.truncate(MAX_WORDS);
+
+    let mut first_err = None;
+    for end in ends.iter().rev() {
+        match parse(&s[..*end]) {
+            Ok(parsed) => return Ok((parsed, s[*end..].trim_start())),
+            Err(err) => first_err = Some(err),
+        }
+    }
+
+    Err(first_err.unwrap_or_else(|| format_err!("No time given")))
+}
+
+/// Parse the longest time expression at the start of s, returning the time
+/// and the rest of s.
+pub fn parse_when_prefix<'a>(s: &'a str, now: &DateTime<Tz>) -> Result<(When, &'a str)> {
+    parse_prefix(s, |prefix| parse_when(prefix, now))
+}
+
+/// How often a recurring reminder repeats, like "every 30m", "every weekday
+/// at 9:55" or "every month on the 1st".
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recurrence {
+    /// A fixed amount of time after the previous occurrence.
+    Interval(Duration),
+
+    /// At a local time on some days of the week.
+    Weekly { days: Vec<Weekday>, time: NaiveTime },
+
+    /// At a local time on a day of the month. Months which are too short use
+    /// their last day instead.
+    Monthly { day: u32, time: NaiveTime },
+}
+
+const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+const WEEKEND: [Weekday; 2] = [Weekday::Sat, Weekday::Sun];
+
+impl Recurrence {
+    /// The first occurrence after both the last one and now.
+    pub fn next(&self, last: &DateTime<Tz>, now: &DateTime<Tz>) -> Result<DateTime<Tz>> {
+        let after = if last > now { last } else { now };
+        let tz = now.timezone();
+
+        // Times skipped by daylight saving time are moved an hour later, so
+        // the reminder still happens that day.
+        let localize = |datetime: NaiveDateTime| {
+            tz.from_local_datetime(&datetime).earliest().or_else(|| {
+                tz.from_local_datetime(&(datetime + TimeDelta::hours(1)))
+                    .earliest()
+            })
+        };
+
+        match self {
+            Recurrence::Interval(interval) => {
+                let interval = TimeDelta::from_std(*interval)?;
+
+                // Skip any occurrences which were missed while the bot was
+                // down rather than sending them all at once.
+                let missed = (*now - *last).num_seconds().max(0) / interval.num_seconds();
+                let steps = i32::try_from(missed + 1)?;
+
+                Ok(*last + interval * steps)
+            }
+            Recurrence::Weekly { days, time } => (0..=7)
+                .map(|offset| after.date_naive() + Days::new(offset))
+                .filter(|date| days.contains(&date.weekday()))
+                .filter_map(|date| localize(date.and_time(*time)))
+                .find(|at| at > after)
+                .ok_or_else(|| format_err!("No upcoming day for {}", self)),
+            Recurrence::Monthly { day, time } => {
+                let first_of_month = after.date_naive().with_day(1).unwrap();
+                (0..=12)
+                    .filter_map(|offset| first_of_month.checked_add_months(Months::new(offset)))
+                    .filter_map(|first| {
+                        let last_day = first.checked_add_months(Months::new(1))?.pred_opt()?;
+                        let date = first.with_day((*day).min(last_day.day()))?;
+                        localize(date.and_time(*time))
+                    })
+                    .find(|at| at > after)
+                    .ok_or_else(|| format_err!("No upcoming day for {}", self))
+            }
+        }
+    }
+}
+
+fn format_compact_duration(duration: &Duration) -> String {
+    let mut secs = duration.as_secs();
+    let mut out = String::new();
+
+    for (unit, unit_secs) in [
+        ("w", 604800),
+        ("d", 86400),
+        ("h", 3600),
+        ("m", 60),
+        ("s", 1),
+    ] {
+        if secs >= unit_secs {
+            out.push_str(&format!("{}{}", secs / unit_secs, unit));
+            secs %= unit_secs;
+        }
+    }
+
+    out
+}
+
+fn ordinal_suffix(n: u32) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Interval(interval) => {
+                write!(f, "every {}", format_compact_duration(interval))
+            }
+            Recurrence::Weekly { days, time } => {
+                if days.len() == 7 {
+                    write!(f, "every day")?;
+                } else if days[..] == WEEKDAYS {
+                    write!(f, "every weekday")?;
+                } else if days[..] == WEEKEND {
+                    write!(f, "every weekend")?;
+                } else {
+                    let days = days.iter().map(|day| day.to_string().to_lowercase());
+                    write!(f, "every {}", days.format(", "))?;
+                }
+                write!(f, " at {}", time.format("%H:%M"))
+            }
+            Recurrence::Monthly { day, time } => write!(
+                f,
+                "every month on the {}{} at {}",
+                day,
+                ordinal_suffix(*day),
+                time.format("%H:%M")
+            ),
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = anyhow::Error;
+
+    /// Parse a recurrence like "every 30m", "every 2 hours", "every day at
+    /// 9am", "every weekday at 9:55", "every mon, wed and fri at 14:00" or
+    /// "every month on the 15th".
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+        let s = s
+            .strip_prefix("every ")
+            .ok_or_else(|| format_err!("Recurring reminders start with \"every\""))?;
+
+        if let Ok(interval) = parse_duration(s) {
+            if interval.as_secs() < MIN_INTERVAL_SECS {
+                return Err(format_err!("Reminders can repeat at most once a minute"));
+            }
+            return Ok(Recurrence::Interval(interval));
+        }
+
+        let s = s.replace(',', " ");
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+
+        let mut days = Vec::new();
+        let mut monthly = false;
+        let mut day_of_month = None;
+        let mut clock = None;
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            let next = tokens.get(i + 1).copied();
+            i += 1;
+
+            // The time ends the rule, so anything after it is left for the
+            // message, like "sat" in "every monday at 9am sat down with the
+            // team".
+            if clock.is_some() {
+                return Err(format_err!(
+                    "Couldn't understand \"{}\" after the time",
+                    token
+                ));
+            }
+
+            if matches!(token, "and" | "at" | "on" | "the") {
+                continue;
+            }
+
+            if clock.is_none() {
+                if let Some(time) = next
+                    .filter(|next| matches!(*next, "am" | "pm"))
+                    .and_then(|meridiem| parse_clock(token, Some(meridiem)))
+                {
+                    clock = Some(time);
+                    i += 1;
+                    continue;
+                }
+
+                if let Some(time) = parse_clock(token, None) {
+                    clock = Some(time);
+                    continue;
+                }
+            }
+
+            match token {
+                "day" => days.extend(WEEKDAYS.iter().chain(WEEKEND.iter())),
+                "weekday" => days.extend(WEEKDAYS),
+                "weekend" => days.extend(WEEKEND),
+                "month" => monthly = true,
+                _ => {
+                    if let Some(weekday) =
+                        parse_weekday(token).or_else(|| parse_weekday(token.strip_suffix('s')?))
+                    {
+                        days.push(weekday);
+                    } else if let Some(day) = parse_day_of_month(token).filter(|_| monthly) {
+                        if day_of_month.replace(day).is_some() {
+                            return Err(format_err!("More than one day of the month given"));
+                        }
+                    } else {
+                        return Err(format_err!("Couldn't understand \"{}\"", token));
+                    }
+                }
+            }
+        }
+
+        let time = clock.unwrap_or_else(|| NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0).unwrap());
+
+        match (monthly, day_of_month) {
+            (true, Some(day)) if days.is_empty() => Ok(Recurrence::Monthly { day, time }),
+            (true, None) => Err(format_err!(
+                "Monthly reminders need a day, like \"every month on the 1st\""
+            )),
+            (true, Some(_)) => Err(format_err!(
+                "Reminders can repeat on days of the week or of the month, but not both"
+            )),
+            (false, _) if days.is_empty() => Err(format_err!(
+                "Couldn't understand how often to repeat. Try \"every 30m\", \"every weekday at 9am\" or \"every month on the 1st\""
+            )),
+            (false, _) => {
+                days.sort_by_key(|day| day.num_days_from_monday());
+                days.dedup();
+                Ok(Recurrence::Weekly { days, time })
+            }
+        }
+    }
+}
+
+/// Parse the longest recurrence at the start of s, returning it and the rest
+/// of s.
+pub fn parse_recurrence_prefix(s: &str) -> Result<(Recurrence, &str)> {
+    parse_prefix(s, |prefix| prefix.parse())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Friday, October 16th 2026 at noon in Chicago.
+    fn now() -> DateTime<Tz> {
+        chrono_tz::America::Chicago
+            .with_ymd_and_hms(2026, 10, 16, 12, 0, 0)
+            .unwrap()
+    }
+
+    fn local(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        chrono_tz::America::Chicago
+            .with_ymd_and_hms(2026, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        // Valid cases: (input, expected_seconds)
+        let valid_cases = [
+            ("5s", 5),
+            ("90s", 90), // More than 1 minute
+            ("1m", 60),
+            ("5m", 300),
+            ("90m", 5400), // More than 1 hour
+            ("2h", 7200),
+            ("25h", 90000), // More than 1 day
+            ("1d", 86400),
+            ("1w", 604800),
+            // Case insensitive and whitespace
+            ("5M", 300),
+            (" 2h ", 7200),
+            // Compound durations
+            ("1h30m", 5400),
+            ("1h 30m", 5400),
+            ("1d2h3m4s", 93784),
+            ("1w1d", 691200),
+            // Unit words
+            ("2 hours", 7200),
+            ("1 hour 15 minutes", 4500),
+            ("10mins", 600),
+            ("3 days", 259200),
+        ];
+
+        for (input, expected) in valid_cases {
+            assert_eq!(
+                parse_duration(input).unwrap(),
+                Duration::from_secs(expected),
+                "Failed parsing '{}'",
+                input
+            );
+        }
+
+        // Invalid cases
+        let invalid_cases = [
+            "",
+            "x",
+            "5",
+            "xm",
+            "5x",
+            "-5m",
+            "1h30",
+            "h30m",
+            "2 things",
+            "1h 30",
+            "99999999999999999999w",
+            "999999999999999w",
+        ];
+        for input in invalid_cases {
+            assert!(
+                parse_duration(input).is_err(),
+                "Expected '{}' to fail parsing",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_clock() {
+        let valid_cases = [
+            ("9am", None, (9, 0)),
+            ("9a", None, (9, 0)),
+            ("12am", None, (0, 0)),
+            ("12pm", None, (12, 0)),
+            ("9:30pm", None, (21, 30)),
+            ("9", Some("pm"), (21, 0)),
+            ("14:00", None, (14, 0)),
+            ("0:05", None, (0, 5)),
+            ("noon", None, (12, 0)),
+            ("midnight", None, (0, 0)),
+        ];
+
+        for (input, meridiem, (hour, minute)) in valid_cases {
+            assert_eq!(
+                parse_clock(input, meridiem),
+                NaiveTime::from_hms_opt(hour, minute, 0),
+                "Failed parsing '{}'",
+                input
+            );
+        }
+
+        let invalid_cases = [
+            ("9", None),
+            ("13pm", None),
+            ("0am", None),
+            ("24:00", None),
+            ("9:5", None),
+            ("9:60", None),
+            ("14:00", Some("pm")),
+            ("noon", Some("am")),
+            ("friday", None),
+        ];
+        for (input, meridiem) in invalid_cases {
+            assert_eq!(
+                parse_clock(input, meridiem),
+                None,
+                "Expected '{}' to fail parsing",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_when() {
+        let valid_cases = [
+            // Relative times
+            ("5m", local(10, 16, 12, 5), true),
+            ("in 1h30m", local(10, 16, 13, 30), true),
+            ("in 2 days", local(10, 18, 12, 0), true),
+            // Clock times are the next time they come around
+            ("at 17:30", local(10, 16, 17, 30), false),
+            ("5pm", local(10, 16, 17, 0), false),
+            ("at 9am", local(10, 17, 9, 0), false),
+            ("at 9 am", local(10, 17, 9, 0), false),
+            ("at noon", local(10, 17, 12, 0), false),
+            // Days
+            ("today at 3pm", local(10, 16, 15, 0), false),
+            ("tomorrow", local(10, 17, 9, 0), false),
+            ("tomorrow at 9am", local(10, 17, 9, 0), false),
+            ("Tomorrow 14:00", local(10, 17, 14, 0), false),
+            ("at 8pm tomorrow", local(10, 17, 20, 0), false),
+            ("monday", local(10, 19, 9, 0), false),
+            ("on wed at 10:15", local(10, 21, 10, 15), false),
+            // Today is Friday, so "friday" is later today if there's time
+            // left, otherwise next week.
+            ("friday 14:00", local(10, 16, 14, 0), false),
+            ("friday 9am", local(10, 23, 9, 0), false),
+            ("next friday 14:00", local(10, 23, 14, 0), false),
+            ("next mon", local(10, 19, 9, 0), false),
+            // Dates
+            ("2026-12-25", local(12, 25, 9, 0), false),
+            ("2026-12-25 8pm", local(12, 25, 20, 0), false),
+            ("dec 25 at noon", local(12, 25, 12, 0), false),
+            ("25th december", local(12, 25, 9, 0), false),
+            ("oct 20", local(10, 20, 9, 0), false),
+            // Times across the end of daylight saving time keep their
+            // local clock time.
+            ("nov 2 at 9am", local(11, 2, 9, 0), false),
+        ];
+
+        for (input, expected, relative) in valid_cases {
+            assert_eq!(
+                parse_when(input, &now()).unwrap(),
+                When {
+                    at: expected,
+                    relative
+                },
+                "Failed parsing '{}'",
+                input
+            );
+        }
+
+        // Month and day without a year which have already passed are next
+        // year.
+        assert_eq!(
+            parse_when("jan 5", &now()).unwrap().at,
+            chrono_tz::America::Chicago
+                .with_ymd_and_hms(2027, 1, 5, 9, 0, 0)
+                .unwrap()
+        );
+
+        // Feb 29 is the next leap year's.
+        assert_eq!(
+            parse_when("feb 29", &now()).unwrap().at,
+            chrono_tz::America::Chicago
+                .with_ymd_and_hms(2028, 2, 29, 9, 0, 0)
+                .unwrap()
+        );
+
+        let invalid_cases = [
+            "",
+            "at",
+            "someday",
+            "today at 9am",
+            "2026-01-01",
+            "feb 30",
+            "friday monday",
+            "next",
+            "next week",
+            "at 25:00",
+            "5",
+            "tomorrow lunch",
+        ];
+        for input in invalid_cases {
+            assert!(
+                parse_when(input, &now()).is_err(),
+                "Expected '{}' to fail parsing",
+                input
+            );
+        }
+
+        // Times which are skipped by daylight saving time don't exist.
+        let before_dst = chrono_tz::America::Chicago
+            .with_ymd_and_hms(2027, 3, 13, 12, 0, 0)
+            .unwrap();
+        assert!(parse_when("tomorrow at 2:30am", &before_dst).is_err());
+    }
+
+    #[test]
+    fn test_parse_when_prefix() {
+        let cases = [
+            ("5m check the oven", local(10, 16, 12, 5), "check the oven"),
+            ("1h 30m stretch", local(10, 16, 13, 30), "stretch"),
+            ("tomorrow at 9am standup", local(10, 17, 9, 0), "standup"),
+            ("tomorrow to check", local(10, 17, 9, 0), "to check"),
+            ("friday 14:00 deploy", local(10, 16, 14, 0), "deploy"),
+            ("at 17:30 go home", local(10, 16, 17, 30), "go home"),
+            ("in 2 hours  call mom", local(10, 16, 14, 0), "call mom"),
+            ("5m", local(10, 16, 12, 5), ""),
+        ];
+
+        for (input, expected, rest) in cases {
+            let (when, remaining) = parse_when_prefix(input, &now()).unwrap();
+            assert_eq!(
+                (when.at, remaining),
+                (expected, rest),
+                "Failed parsing '{}'",
+                input
+            );
+        }
+
+        assert!(parse_when_prefix("soon do the thing", &now()).is_err());
+        assert!(parse_when_prefix("", &now()).is_err());
+    }
+
+    #[test]
+    fn test_parse_recurrence() {
+        let at = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+
+        let valid_cases = [
+            (
+                "every 30m",
+                Recurrence::Interval(Duration::from_secs(1800)),
+                "every 30m",
+            ),
+            (
+                "every 1h 30m",
+                Recurrence::Interval(Duration::from_secs(5400)),
+                "every 1h30m",
+            ),
+            (
+                "Every 2 days",
+                Recurrence::Interval(Duration::from_secs(172800)),
+                "every 2d",
+            ),
+            (
+                "every day",
+                Recurrence::Weekly {
+                    days: WEEKDAYS.iter().chain(WEEKEND.iter()).copied().collect(),
+                    time: at(9, 0),
+                },
+                "every day at 09:00",
+            ),
+            (
+                "every weekday at 9:55",
+                Recurrence::Weekly {
+                    days: WEEKDAYS.to_vec(),
+                    time: at(9, 55),
+                },
+                "every weekday at 09:55",
+            ),
+            (
+                "every weekend at noon",
+                Recurrence::Weekly {
+                    days: WEEKEND.to_vec(),
+                    time: at(12, 0),
+                },
+                "every weekend at 12:00",
+            ),
+            (
+                "every fri, mon and wed at 2 pm",
+                Recurrence::Weekly {
+                    days: vec![Weekday::Mon, Weekday::Wed, Weekday::Fri],
+                    time: at(14, 0),
+                },
+                "every mon, wed, fri at 14:00",
+            ),
+            (
+                "every tuesdays 17:30",
+                Recurrence::Weekly {
+                    days: vec![Weekday::Tue],
+                    time: at(17, 30),
+                },
+                "every tue at 17:30",
+            ),
+            (
+                "every month on the 1st",
+                Recurrence::Monthly {
+                    day: 1,
+                    time: at(9, 0),
+                },
+                "every month on the 1st at 09:00",
+            ),
+            (
+                "every month on the 22nd at 8pm",
+                Recurrence::Monthly {
+                    day: 22,
+                    time: at(20, 0),
+                },
+                "every month on the 22nd at 20:00",
+            ),
+        ];
+
+        for (input, expected, display) in valid_cases {
+            let recurrence: Recurrence = input
+                .parse()
+                .unwrap_or_else(|e| panic!("Failed parsing '{}': {}", input, e));
+            assert_eq!(recurrence, expected, "Failed parsing '{}'", input);
+            assert_eq!(recurrence.to_string(), display);
+
+            // What we show is also what we store, so it needs to parse back
+            // to the same thing.
+            assert_eq!(display.parse::<Recurrence>().unwrap(), expected);
+        }
+
+        let invalid_cases = [
+            "",
+            "every",
+            "30m",
+            "every 30s",
+            "every hour",
+            "every blue moon",
+            "every month",
+            "every month on the 32nd",
+            "every month on the 1st and 15th",
+            "every monday on the 1st",
+            "every monday at 25:00",
+            "every at 9am",
+            "every monday at 9am sat",
+            "every day at 9am and",
+        ];
+        for input in invalid_cases {
+            assert!(
+                input.parse::<Recurrence>().is_err(),
+                "Expected '{}' to fail parsing",
+                input
+            );
+        }
+
+        let (recurrence, rest) = parse_recurrence_prefix("every weekday at 9:55 standup").unwrap();
+        assert_eq!(recurrence.to_string(), "every weekday at 09:55");
+        assert_eq!(rest, "standup");
+
+        let (recurrence, rest) =
+            parse_recurrence_prefix("every monday at 9am sat down with team").unwrap();
+        assert_eq!(recurrence.to_string(), "every mon at 09:00");
+        assert_eq!(rest, "sat down with team");
+
+        let (recurrence, rest) = parse_recurrence_prefix("every 2 hours drink water").unwrap();
+        assert_eq!(recurrence.to_string(), "every 2h");
+        assert_eq!(rest, "drink water");
+    }
+
+    #[test]
+    fn test_recurrence_next() {
+        let next = |rule: &str, last: DateTime<Tz>| {
+            rule.parse::<Recurrence>()
+                .unwrap()
+                .next(&last, &now())
+                .unwrap()
+        };
+
+        // The first occurrence is computed from now.
+        assert_eq!(next("every 30m", now()), local(10, 16, 12, 30));
+        assert_eq!(next("every weekday at 9:55", now()), local(10, 19, 9, 55));
+        assert_eq!(next("every weekday at 14:00", now()), local(10, 16, 14, 0));
+        assert_eq!(next("every sat, sun at 8am", now()), local(10, 17, 8, 0));
+        assert_eq!(next("every fri at noon", now()), local(10, 23, 12, 0));
+        assert_eq!(next("every month on the 20th", now()), local(10, 20, 9, 0));
+
+        // Short months use their last day.
+        assert_eq!(
+            next("every month on the 31st", local(10, 31, 9, 0)),
+            local(11, 30, 9, 0)
+        );
+        assert_eq!(next("every month on the 15th", now()), local(11, 15, 9, 0));
+
+        // Intervals count from the last occurrence, skipping any which were
+        // missed.
+        assert_eq!(
+            next("every 1h", local(10, 16, 11, 30)),
+            local(10, 16, 12, 30)
+        );
+        assert_eq!(
+            next("every 1h", local(10, 16, 9, 15)),
+            local(10, 16, 12, 15)
+        );
+
+        // Later occurrences keep their local time across the end of
+        // daylight saving time.
+        let last = local(10, 30, 9, 0);
+        let after_dst = chrono_tz::America::Chicago
+            .with_ymd_and_hms(2026, 11, 2, 9, 0, 0)
+            .unwrap();
+        assert_eq!(
+            "every weekday at 9am"
+                .parse::<Recurrence>()
+                .unwrap()
+                .next(&last, &last)
+                .unwrap(),
+            after_dst
+        );
+
+        // Times skipped by daylight saving time move an hour later.
+        let before_dst = chrono_tz::America::Chicago
+            .with_ymd_and_hms(2027, 3, 13, 12, 0, 0)
+            .unwrap();
+        assert_eq!(
+            "every day at 2:30am"
+                .parse::<Recurrence>()
+                .unwrap()
+                .next(&before_dst, &before_dst)
+                .unwrap(),
+            chrono_tz::America::Chicago
+                .with_ymd_and_hms(2027, 3, 14, 3, 30, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let cases = [
+            (1, "1 second"),
+            (5, "5 seconds"),
+            (60, "1 minute"),
+            (300, "5 minutes"),
+            (3600, "1 hour"),
+            (7200, "2 hours"),
+            (86400, "1 day"),
+            (172800, "2 days"),
+            (-60, "1 minute"), // negative values use unsigned_abs
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                format_duration(input),
+                expected,
+                "Failed formatting {}",
+                input
+            );
+        }
+    }
+}